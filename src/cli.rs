@@ -1,4 +1,7 @@
-use crate::{config::Config, Context, FULL_VERSION, LONG_VERSION};
+use crate::config::{
+    Config, Mount, PrivilegeMode, SELinuxMode, SecretSource, SkelMode, WorkspaceMode,
+};
+use crate::{Context, FULL_VERSION, LONG_VERSION};
 use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
@@ -90,11 +93,25 @@ pub struct CmdStartArgs {
     #[arg(long)]
     pub shell: Option<String>,
 
-    /// Path to directory which will be used as /etc/skel inside the container
+    /// Directory with dotfiles copied into user home after the image `/etc/skel` (can be used
+    /// multiple times, later ones win)
     ///
-    /// Used for static dotfiles that can be copied verbatim
+    /// Files ending with `.tmpl` are rendered with the same variables as the config
     #[arg(long, value_name = "DIR")]
-    pub skel: Option<String>,
+    pub skel: Vec<String>,
+
+    /// How the skel files are put into the home (default: copy)
+    ///
+    /// With `symlink` changes on the host show up live, templates are still rendered
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub skel_mode: Option<SkelMode>,
+
+    /// How the project directory is mounted (default: rw)
+    ///
+    /// With `overlay` all writes go to a throwaway overlay, use `diff` command to inspect and apply
+    /// the changes before the container is stopped
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub workspace: Option<WorkspaceMode>,
 
     /// Run command on init, ran before all other scripts (ran using `/bin/sh`)
     #[arg(long, value_name = "COMMAND")]
@@ -104,14 +121,38 @@ pub struct CmdStartArgs {
     #[arg(long, value_name = "COMMAND")]
     pub on_init_post: Vec<String>,
 
-    /// Mount additional paths inside workspace
-    #[arg(short, long, value_name = "DIRECTORY")]
-    pub mount: Vec<String>,
+    /// Install package on init using the detected package manager
+    #[arg(long = "package", value_name = "PACKAGE", value_parser = parse_package)]
+    pub packages: Vec<String>,
+
+    /// Mount additional directory inside workspace, or a file or directory at a destination
+    /// relative to home or absolute
+    ///
+    /// Options are comma separated: ro, rw, noexec, exec, use empty destination to set them for a
+    /// workspace mount (`SRC[=ALIAS]::OPTIONS`)
+    #[arg(short, long, value_name = "SRC[:DST[:OPTIONS]]", value_parser = parse_mount)]
+    pub mount: Vec<Mount>,
 
     /// Environment variables to set inside the container
     #[arg(short, long, value_name = "VAR=VALUE")]
     pub env: Vec<String>,
 
+    /// Read environment variables from a dotenv file, values are not passed on the command line
+    #[arg(long, value_name = "FILE")]
+    pub env_file: Vec<String>,
+
+    /// Forward host environment variables whose name matches the pattern (`*` and `?` wildcards)
+    #[arg(long, value_name = "PATTERN")]
+    pub env_passthrough: Vec<String>,
+
+    /// Mount secret as file `/run/secrets/NAME`, its value never appears on the command line
+    #[arg(long = "secret", value_name = "NAME=file:PATH|command:CMD|env:VAR", value_parser = parse_secret)]
+    pub secrets: Vec<(String, SecretSource)>,
+
+    /// Use a named volume shared by containers of the same config (or image) as the cache dir
+    #[arg(long, value_name = "BOOL", default_missing_value = "true", require_equals = true, num_args = 0..=1)]
+    pub cache_volume: Option<bool>,
+
     /// Set network access permission for the container
     #[arg(long, value_name = "BOOL", default_missing_value = "true", require_equals = true, num_args = 0..=1, help_heading = START_HEADING_PERMISSIONS)]
     pub network: Option<bool>,
@@ -130,6 +171,14 @@ pub struct CmdStartArgs {
     #[arg(long, value_name = "BOOL", default_missing_value = "true", require_equals = true, num_args = 0..=1, help_heading = START_HEADING_PERMISSIONS)]
     pub wayland: Option<bool>,
 
+    /// Passes X11 display socket through with an untrusted Xauthority cookie, allows clipboard
+    /// access
+    ///
+    /// Untrusted clients cannot capture other windows but some extensions (like GLX) are not
+    /// available to them
+    #[arg(long, value_name = "BOOL", default_missing_value = "true", require_equals = true, num_args = 0..=1, help_heading = START_HEADING_PERMISSIONS)]
+    pub x11: Option<bool>,
+
     /// Pass through ssh-agent socket
     #[arg(long, value_name = "BOOL", default_missing_value = "true", require_equals = true, num_args = 0..=1, help_heading = START_HEADING_PERMISSIONS)]
     pub ssh_agent: Option<bool>,
@@ -150,6 +199,32 @@ pub struct CmdStartArgs {
     #[arg(long = "cap", value_name = "[!]CAPABILITY", value_delimiter = ',', help_heading = START_HEADING_PERMISSIONS)]
     pub capabilities: Vec<String>,
 
+    /// Prevent processes from gaining more privileges, breaks `sudo` and `su` inside the container
+    ///
+    /// Always enabled with privilege mode `none`
+    #[arg(long, value_name = "BOOL", default_missing_value = "true", require_equals = true, num_args = 0..=1, help_heading = START_HEADING_PERMISSIONS)]
+    pub no_new_privileges: Option<bool>,
+
+    /// Use custom seccomp profile (JSON) instead of the engine default
+    #[arg(long, value_name = "FILE", help_heading = START_HEADING_PERMISSIONS)]
+    pub seccomp_profile: Option<String>,
+
+    /// Deny group of syscalls, or allow them again by prefixing `!group`
+    ///
+    /// Valid groups: ptrace, mount, bpf, keyring
+    #[arg(long = "deny-syscalls", value_name = "[!]GROUP", value_delimiter = ',', help_heading = START_HEADING_PERMISSIONS)]
+    pub deny_syscalls: Vec<String>,
+
+    /// SELinux labeling of the workspace and mounts (default: disable)
+    ///
+    /// Passing through sockets may not work when labeling is enabled
+    #[arg(long, value_enum, value_name = "MODE", help_heading = START_HEADING_PERMISSIONS)]
+    pub selinux: Option<SELinuxMode>,
+
+    /// How the user can gain root privileges inside the container (default: sudo)
+    #[arg(long, value_enum, value_name = "MODE", help_heading = START_HEADING_PERMISSIONS)]
+    pub privilege: Option<PrivilegeMode>,
+
     /// File, image or config to use to start a container
     #[arg(env = crate::ENV_IMAGE, value_parser = ConfigArg::parse, value_name = "FILE|IMAGE|@CONFIG")]
     pub config: ConfigArg,
@@ -159,20 +234,56 @@ pub struct CmdStartArgs {
     pub engine_args: Vec<String>,
 }
 
+impl CmdStartArgs {
+    /// Names of all permissions as used in config
+    pub const PERMISSIONS: [&'static str; 7] = [
+        "network",
+        "pipewire",
+        "pulseaudio",
+        "wayland",
+        "x11",
+        "ssh_agent",
+        "session_bus",
+    ];
+
+    /// Returns all permissions with their names as used in config
+    pub fn permissions(&self) -> [(&'static str, bool); 7] {
+        let values = [
+            self.network,
+            self.pipewire,
+            self.pulseaudio,
+            self.wayland,
+            self.x11,
+            self.ssh_agent,
+            self.session_bus,
+        ];
+
+        std::array::from_fn(|i| (Self::PERMISSIONS[i], values[i].unwrap_or(false)))
+    }
+}
+
 fn parse_ports(input: &str) -> Result<(u32, u32), String> {
-    let parse_port = |raw: &str| -> Result<u32, String> {
-        raw.parse::<u32>()
-            .map_err(|_| format!("Invalid port {:?}", raw))
+    crate::config::parse_port_mapping(input)
+}
+
+fn parse_package(input: &str) -> Result<String, String> {
+    crate::config::validate_package_name(input)?;
+
+    Ok(input.to_string())
+}
+
+fn parse_mount(input: &str) -> Result<Mount, String> {
+    input.parse()
+}
+
+fn parse_secret(input: &str) -> Result<(String, SecretSource), String> {
+    let Some((name, source)) = input.split_once('=') else {
+        return Err(format!("Invalid secret {:?}, expected NAME=SOURCE", input));
     };
 
-    if let Some((left_raw, right_raw)) = input.split_once(":") {
-        Ok((parse_port(left_raw)?, parse_port(right_raw)?))
-    } else {
-        let port = parse_port(input)?;
+    crate::config::validate_secret_name(name)?;
 
-        // map it to itself
-        Ok((port, port))
-    }
+    Ok((name.to_string(), source.parse()?))
 }
 
 #[derive(Args, Debug, Clone)]
@@ -212,6 +323,7 @@ pub struct CmdExistsArgs {
 }
 
 #[derive(Args, Debug, Clone)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CmdConfigArgs {
     /// Show all options for a config
     #[clap(short, long, exclusive = true)]
@@ -221,9 +333,76 @@ pub struct CmdConfigArgs {
     #[clap(short, long, exclusive = true)]
     pub example: bool,
 
+    /// Print JSON Schema of the latest config version
+    ///
+    /// Can be used with taplo or other editors by adding `#:schema <path>` at the top of the config
+    #[clap(long, exclusive = true)]
+    pub schema: bool,
+
+    /// Print security risk report of the config instead
+    #[clap(long, requires = "config")]
+    pub audit: bool,
+
+    /// Exit with error if any audit finding has higher risk than this
+    #[clap(long, value_enum, value_name = "LEVEL", requires = "audit")]
+    pub max_risk: Option<crate::commands::RiskLevel>,
+
+    /// Recreate config from settings of a running container
+    #[clap(long, value_name = "CONTAINER", conflicts_with_all = ["config", "audit"])]
+    pub from_container: Option<String>,
+
     /// Path to file, name of image or @config to inspect
-    #[clap(value_parser = ConfigArg::parse, value_name = "FILE|IMAGE|@CONFIG", required_unless_present_any(["options", "example"]))]
+    #[clap(value_parser = ConfigArg::parse, value_name = "FILE|IMAGE|@CONFIG", required_unless_present_any(["options", "example", "schema", "from_container"]))]
     pub config: Option<ConfigArg>,
+
+    #[command(subcommand)]
+    pub cmd: Option<ConfigCommands>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommands {
+    /// Upgrade config files to the latest version, comments are preserved where possible
+    ///
+    /// Prints the migrated config unless `--check` or `--in-place` is used
+    Migrate(CmdConfigMigrateArgs),
+
+    /// Create a new config interactively or from start args
+    New(CmdConfigNewArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CmdConfigNewArgs {
+    /// Overwrite the config if it already exists
+    #[arg(short, long)]
+    pub force: bool,
+
+    /// Create the config from start args instead of asking interactively
+    ///
+    /// Example: `arcam config new dev --from-args -- --network --port=8080 fedora`
+    #[arg(long, requires = "start_args")]
+    pub from_args: bool,
+
+    /// Name of the config, if not set the config is written to `.arcam.toml` in current directory
+    pub name: Option<String>,
+
+    /// Same args as for the start command, `arcam start` prefix is optional
+    #[arg(last = true, value_name = "START ARGS", requires = "from_args")]
+    pub start_args: Vec<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CmdConfigMigrateArgs {
+    /// Do not write anything, exit with error if any config is not the latest version
+    #[arg(long, conflicts_with = "in_place")]
+    pub check: bool,
+
+    /// Overwrite the config files with the migrated version
+    #[arg(short, long)]
+    pub in_place: bool,
+
+    /// Path to file or @config to migrate
+    #[arg(value_parser = ConfigArg::parse, value_name = "FILE|@CONFIG", required = true)]
+    pub configs: Vec<ConfigArg>,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -245,6 +424,24 @@ pub struct CmdLogsArgs {
     #[arg(short, long)]
     pub follow: bool,
 
+    /// Show output of the container initialization instead
+    #[arg(long)]
+    pub init: bool,
+
+    #[arg(value_name = "CONTAINER", default_value = "", env = crate::ENV_CONTAINER)]
+    pub name: String,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CmdDiffArgs {
+    /// Apply changes to path back to the project directory, directories are applied recursively
+    #[arg(long, value_name = "PATH")]
+    pub apply: Vec<String>,
+
+    /// Apply all the changes back to the project directory
+    #[arg(long, conflicts_with = "apply")]
+    pub apply_all: bool,
+
     #[arg(value_name = "CONTAINER", default_value = "", env = crate::ENV_CONTAINER)]
     pub name: String,
 }
@@ -274,6 +471,8 @@ pub struct CmdCompletionArgs {
     pub complete: Option<crate::commands::ShellCompletionType>,
 }
 
+// NOTE: the enum is only constructed once so the size does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug, Clone)]
 pub enum CliCommands {
     /// Start a container in current directory, mounting it read-write
@@ -301,6 +500,9 @@ pub enum CliCommands {
     /// Show container logs in journalctl
     Logs(CmdLogsArgs),
 
+    /// Show changes made to the project directory in overlay workspace mode
+    Diff(CmdDiffArgs),
+
     /// Stop running container
     #[clap(visible_alias = "stop")]
     Kill(CmdKillArgs),
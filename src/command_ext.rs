@@ -39,6 +39,9 @@ pub trait CommandExt {
     /// Same as `log_output` but anyhow error
     fn log_output_anyhow(&mut self) -> Result<Output>;
 
+    /// Same as `log_output` but stdout is not logged, use when it contains secrets
+    fn log_output_redacted(&mut self) -> std::io::Result<Output>;
+
     /// Logs command and output after running `Command::status`
     fn log_status(&mut self) -> std::io::Result<ExitStatus>;
 
@@ -101,6 +104,25 @@ impl CommandExt for Command {
         }
     }
 
+    fn log_output_redacted(&mut self) -> std::io::Result<Output> {
+        let output = self.output();
+        match output.as_ref() {
+            Ok(output) => log::debug!(
+                "Command {:?} (output)\n  STDOUT: <redacted>\n  STDERR: {:?}\n  STATUS: {:?}",
+                self.get_full_command(),
+                String::from_utf8_lossy(&output.stderr),
+                output.status,
+            ),
+            Err(err) => log::debug!(
+                "Command {:?} (output)\n  ERROR: {:?}",
+                self.get_full_command(),
+                err,
+            ),
+        }
+
+        output
+    }
+
     fn log_status(&mut self) -> std::io::Result<ExitStatus> {
         let status = self.status();
 
@@ -1,6 +1,7 @@
 mod cmd_completion_generator;
 mod cmd_completion_helper;
 mod cmd_config;
+mod cmd_diff;
 mod cmd_exec;
 mod cmd_exists;
 mod cmd_init;
@@ -12,7 +13,8 @@ mod cmd_start;
 
 pub use cmd_completion_generator::shell_completion_generation;
 pub use cmd_completion_helper::{shell_completion_helper, ShellCompletionType};
-pub use cmd_config::config_command;
+pub use cmd_config::{config_command, RiskLevel};
+pub use cmd_diff::{remove_overlay_dir, workspace_diff};
 pub use cmd_exec::container_exec;
 pub use cmd_exists::container_exists;
 pub use cmd_init::container_init;
@@ -1,9 +1,16 @@
-use crate::cli::{CmdConfigArgs, ConfigArg};
+mod audit;
+mod migrate;
+mod new;
+mod schema;
+
+use crate::cli::{CmdConfigArgs, ConfigArg, ConfigCommands};
 use crate::command_extensions::*;
-use crate::config::{Config, ConfigFile};
+use crate::config::{Config, ConfigFile, PortMapping};
 use crate::prelude::*;
 use code_docs::DocumentedStruct;
 
+pub use audit::RiskLevel;
+
 fn get_image_config(ctx: &Context, image: &str) -> Result<String> {
     let cmd = ctx
         .engine
@@ -37,6 +44,49 @@ fn get_image_config(ctx: &Context, image: &str) -> Result<String> {
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
+/// Recreates config from the label and env of a container started by arcam
+fn config_from_container(ctx: &Context, container: &str) -> Result<Config> {
+    if !ctx.engine.container_exists(container)? {
+        return Err(anyhow!("Container {:?} does not exist", container));
+    }
+
+    let container_info = ctx.engine.inspect_containers(vec![container])?;
+    let container_info = container_info.first().unwrap();
+
+    let Some(raw) = container_info.labels.get(crate::CONTAINER_LABEL_CONFIG) else {
+        if container_info
+            .labels
+            .contains_key(crate::CONTAINER_LABEL_HOST_DIR)
+        {
+            return Err(anyhow!(
+                "Container {:?} was started by an older version of {}",
+                container,
+                crate::APP_NAME
+            ));
+        }
+
+        return Err(anyhow!(
+            "Container {:?} is not owned by {}",
+            container,
+            crate::APP_NAME
+        ));
+    };
+
+    let mut config = serde_json::from_str::<Config>(raw)
+        .with_context(|| format!("Failed to parse config label of container {:?}", container))?;
+
+    // env values are not stored in the label, passthrough vars are kept as they are
+    for (key, value) in config.env.iter_mut().filter(|(_, x)| x.is_empty()) {
+        match container_info.env.get(key) {
+            // it is the expanded value so it must be taken literally
+            Some(x) => *value = x.replace('$', "$$"),
+            None => log::warn!("Env var {key:?} is not set in the container"),
+        }
+    }
+
+    Ok(config)
+}
+
 fn show_options() -> Result<()> {
     // print config version in same style as the rest of options
     println!(
@@ -49,8 +99,8 @@ fn show_options() -> Result<()> {
 
     let iter = Config::field_names()
         .into_iter()
-        .zip(Config::field_types().into_iter())
-        .zip(Config::field_docs().into_iter())
+        .zip(Config::field_types())
+        .zip(Config::field_docs())
         .map(|((name, r#type), docs)| (name, r#type, docs));
 
     // convert some types to be easier to understand for non-rust users
@@ -80,8 +130,9 @@ fn show_example(ctx: &Context) -> Result<()> {
             image: "docker.io/library/debian:latest".into(),
             network: true,
             engine_args: vec!["--privileged".into()],
-            ports: vec![(8080, 8080), (6666, 6666)],
-            env: vec![("LS_COLORS".into(), "rs=0:di=01;34:ln=01;...".into())],
+            ports: vec![PortMapping::new(8080, 8080), PortMapping::new(80, 6666)],
+            env: [("LS_COLORS".into(), "rs=0:di=01;34:ln=01;...".into())].into(),
+            persist: [("cargo".into(), "~/.cargo".into())].into(),
             ..Default::default()
         });
 
@@ -103,7 +154,26 @@ CONFIG DIRECTORY: {cfgdir:?}
     Ok(())
 }
 
+/// Prints which conditional sections were applied to the config
+fn print_matched_conditions(config: &Config) {
+    if config.matched_conditions.is_empty() {
+        return;
+    }
+
+    println!("Matched conditions:");
+    for i in &config.matched_conditions {
+        println!("  {}", i);
+    }
+}
+
 pub fn config_command(ctx: Context, cli_args: CmdConfigArgs) -> Result<()> {
+    if let Some(cmd) = cli_args.cmd {
+        return match cmd {
+            ConfigCommands::Migrate(x) => migrate::migrate_command(&ctx, x),
+            ConfigCommands::New(x) => new::new_command(&ctx, x),
+        };
+    }
+
     // show options and quit
     if cli_args.options {
         return show_options();
@@ -114,20 +184,49 @@ pub fn config_command(ctx: Context, cli_args: CmdConfigArgs) -> Result<()> {
         return show_example(&ctx);
     }
 
+    // print schema and quit
+    if cli_args.schema {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&schema::config_schema()?)?
+        );
+        return Ok(());
+    }
+
+    if let Some(container) = &cli_args.from_container {
+        let config = config_from_container(&ctx, container)?;
+        print!("{}", new::config_to_commented_toml(&config)?);
+
+        return Ok(());
+    }
+
     let config = cli_args.config.unwrap();
 
     // if image is passed extract from image
     if let ConfigArg::Image(image) = &config {
-        println!("Inspecting config from image {:?}", image);
-
         let raw = get_image_config(&ctx, image)?;
-        println!("{:#?}", crate::config::ConfigFile::config_from_str(&raw)?);
+        let config = crate::config::ConfigFile::config_from_str(&raw)?;
+
+        if cli_args.audit {
+            return audit::print_audit(&config, cli_args.max_risk);
+        }
+
+        println!("Inspecting config from image {:?}", image);
+        print_matched_conditions(&config);
+        println!("{:#?}", config);
 
         return Ok(());
     }
 
+    let config = config.into_config(&ctx)?;
+
+    if cli_args.audit {
+        return audit::print_audit(&config, cli_args.max_risk);
+    }
+
     println!("Inspecting config");
-    println!("{:#?}", config.into_config(&ctx)?);
+    print_matched_conditions(&config);
+    println!("{:#?}", config);
 
     Ok(())
 }
@@ -0,0 +1,377 @@
+//! Security audit of a config
+
+use crate::config::{Config, PortMapping, SELinuxMode, SecretSource};
+use crate::engine::args::parse_engine_args;
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Risk level of an audit finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum RiskLevel {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Display for RiskLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Info => "INFO",
+            Self::Low => "LOW",
+            Self::Medium => "MEDIUM",
+            Self::High => "HIGH",
+            Self::Critical => "CRITICAL",
+        })
+    }
+}
+
+/// Single risk found in the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub level: RiskLevel,
+
+    /// Config field that caused the finding
+    pub field: &'static str,
+
+    pub message: String,
+}
+
+impl Finding {
+    fn new(level: RiskLevel, field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            level,
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// Risk of adding a capability
+fn capability_risk(cap: &str) -> RiskLevel {
+    match cap {
+        "ALL" | "SYS_ADMIN" | "SYS_MODULE" | "SYS_RAWIO" => RiskLevel::Critical,
+        "SYS_PTRACE" | "NET_ADMIN" | "DAC_READ_SEARCH" | "SYS_BOOT" | "BPF" | "PERFMON" => {
+            RiskLevel::High
+        }
+        _ => RiskLevel::Medium,
+    }
+}
+
+/// Find all variables referenced in the string, either `$VAR` or `${VAR}`
+fn referenced_vars(input: &str) -> Vec<String> {
+    let mut vars: Vec<String> = vec![];
+
+    for (index, _) in input.match_indices('$') {
+        let rest = input[index + 1..].trim_start_matches('{');
+        let name = rest
+            .chars()
+            .take_while(|x| x.is_ascii_alphanumeric() || *x == '_')
+            .collect::<String>();
+
+        if !name.is_empty() {
+            vars.push(name);
+        }
+    }
+
+    vars
+}
+
+/// Audits the config returning all risks found
+pub fn audit_config(config: &Config) -> Vec<Finding> {
+    use RiskLevel::*;
+
+    let mut findings: Vec<Finding> = vec![];
+
+    if config.host_pre_init.is_some() {
+        findings.push(Finding::new(
+            Critical,
+            "host_pre_init",
+            "Runs an arbitrary script on the host before the container is started",
+        ));
+    }
+
+    // sockets passed through
+    for (enabled, level, field, message) in [
+        (
+            config.session_bus,
+            Critical,
+            "session_bus",
+            "Session D-Bus allows arbitrary command execution on the host (ex. systemd-run)",
+        ),
+        (
+            config.ssh_agent,
+            High,
+            "ssh_agent",
+            "ssh-agent socket allows using your SSH keys to authenticate anywhere while the container runs",
+        ),
+        (
+            config.wayland,
+            High,
+            "wayland",
+            "Wayland socket allows clipboard access and interaction with the compositor",
+        ),
+        (
+            config.x11,
+            High,
+            "x11",
+            "X11 socket allows clipboard access, the untrusted cookie limits access to other windows",
+        ),
+        (
+            config.pipewire,
+            Medium,
+            "pipewire",
+            "Pipewire socket allows recording microphone and possibly screen",
+        ),
+        (
+            config.pulseaudio,
+            Medium,
+            "pulseaudio",
+            "Pulseaudio socket allows recording microphone",
+        ),
+    ] {
+        if enabled {
+            findings.push(Finding::new(level, field, message));
+        }
+    }
+
+    if config.network {
+        findings.push(Finding::new(
+            Low,
+            "network",
+            "Network access allows downloading code and exfiltrating data",
+        ));
+    }
+
+    // resolve capabilities same as start does
+    let mut caps = HashMap::<String, bool>::new();
+    for i in &config.capabilities {
+        let (cap, add) = match i.strip_prefix("!") {
+            Some(x) => (x, false),
+            None => (i.as_str(), true),
+        };
+
+        let cap = cap.to_uppercase();
+        let cap = cap.strip_prefix("CAP_").unwrap_or(&cap).to_string();
+        caps.insert(cap, add);
+    }
+
+    let mut added_caps = caps
+        .into_iter()
+        .filter_map(|(cap, add)| add.then_some(cap))
+        .collect::<Vec<_>>();
+    added_caps.sort();
+
+    for cap in added_caps {
+        findings.push(Finding::new(
+            capability_risk(&cap),
+            "capabilities",
+            format!("Adds capability {}", cap),
+        ));
+    }
+
+    for (flag, value) in parse_engine_args(&config.engine_args) {
+        let value = value.unwrap_or_default();
+        let finding = match (flag.as_str(), value.as_str()) {
+            ("--privileged", _) => Some((Critical, "Disables all sandboxing".to_string())),
+            ("--pid" | "--ipc" | "--uts" | "--userns" | "--cgroupns", "host") => {
+                Some((High, format!("Shares host namespace ({}={})", flag, value)))
+            }
+            ("--network", "host") => Some((
+                High,
+                "Shares host network, allows access to local host services".to_string(),
+            )),
+            ("--volume", x) if x.starts_with("/:") => {
+                Some((Critical, "Mounts host root filesystem".to_string()))
+            }
+            ("--mount", x) if x.split(',').any(|x| x == "source=/" || x == "src=/") => {
+                Some((Critical, "Mounts host root filesystem".to_string()))
+            }
+            ("--volume", x) if x.starts_with(['/', '~', '.']) => {
+                Some((Medium, format!("Mounts host path {:?}", x)))
+            }
+            ("--security-opt", x)
+                if ["seccomp=unconfined", "apparmor=unconfined", "label=disable"].contains(&x) =>
+            {
+                Some((High, format!("Disables security feature ({})", x)))
+            }
+            ("--cap-add", x) => {
+                let cap = x.to_uppercase();
+                let cap = cap.strip_prefix("CAP_").unwrap_or(&cap).to_string();
+                Some((capability_risk(&cap), format!("Adds capability {}", cap)))
+            }
+            ("--device", x) => Some((Medium, format!("Exposes host device {:?}", x))),
+            ("--env-host", _) => Some((Medium, "Leaks whole host environment".to_string())),
+            _ => None,
+        };
+
+        if let Some((level, message)) = finding {
+            findings.push(Finding::new(level, "engine_args", message));
+        }
+    }
+
+    for mount in &config.mounts {
+        let source = mount.source.trim_end_matches('/');
+        let (level, message) = if source.is_empty() {
+            (Critical, "Mounts host root filesystem".to_string())
+        } else if mount.readonly {
+            (
+                Low,
+                format!("Mounts host path {:?} read-only", mount.source),
+            )
+        } else {
+            (Medium, format!("Mounts host path {:?}", mount.source))
+        };
+
+        findings.push(Finding::new(level, "mounts", message));
+    }
+
+    for (key, value) in &config.env {
+        let vars = referenced_vars(value)
+            .into_iter()
+            .filter(|x| !["CONTAINER", "CONTAINER_NAME", "RAND", "RANDOM"].contains(&x.as_str()))
+            .collect::<Vec<_>>();
+
+        if !vars.is_empty() {
+            findings.push(Finding::new(
+                Low,
+                "env",
+                format!(
+                    "Variable {} exposes host values or paths ({})",
+                    key,
+                    vars.join(", ")
+                ),
+            ));
+        }
+    }
+
+    for (name, source) in &config.secrets {
+        let (level, message) = match source {
+            SecretSource::Command(x) => (
+                Critical,
+                format!("Secret {} runs a command on the host ({:?})", name, x),
+            ),
+            SecretSource::File(x) => (Medium, format!("Secret {} exposes host file {:?}", name, x)),
+            SecretSource::Env(x) => (Low, format!("Secret {} exposes host variable {}", name, x)),
+        };
+
+        findings.push(Finding::new(level, "secrets", message));
+    }
+
+    for port in &config.ports {
+        let message = match port {
+            PortMapping::Ports { container, host } => format!(
+                "Publishes port {} on all host interfaces (container port {})",
+                host, container
+            ),
+            PortMapping::Template(x) => {
+                format!("Publishes port {:?} on all host interfaces", x)
+            }
+        };
+
+        findings.push(Finding::new(Low, "ports", message));
+    }
+
+    if config.seccomp_profile.is_some() {
+        findings.push(Finding::new(
+            Info,
+            "seccomp_profile",
+            "Custom seccomp profile replaces the engine default, review it manually",
+        ));
+    }
+
+    if config.selinux == SELinuxMode::Disable {
+        findings.push(Finding::new(
+            Info,
+            "selinux",
+            "SELinux separation is disabled",
+        ));
+    }
+
+    // most severe first
+    findings.sort_by_key(|x| std::cmp::Reverse(x.level));
+
+    findings
+}
+
+/// Prints the audit report, returns error if risk is above the maximum
+pub fn print_audit(config: &Config, max_risk: Option<RiskLevel>) -> Result<()> {
+    let findings = audit_config(config);
+
+    println!(
+        "Security audit of config {:?}",
+        config.name.as_ref().unwrap_or(&config.image)
+    );
+
+    if findings.is_empty() {
+        println!("\nNo risks found");
+        return Ok(());
+    }
+
+    println!();
+    for finding in &findings {
+        println!(
+            "{:<8}  {:<16}  {}",
+            finding.level.to_string(),
+            finding.field,
+            finding.message
+        );
+    }
+
+    // findings are sorted so first one is the highest
+    let highest = findings.first().unwrap().level;
+
+    println!("\nHighest risk: {} ({} findings)", highest, findings.len());
+
+    if let Some(max_risk) = max_risk {
+        if highest > max_risk {
+            return Err(anyhow!(
+                "Config risk {} is above maximum allowed risk {}",
+                highest,
+                max_risk
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audit() {
+        let config = Config {
+            image: "fedora".into(),
+            session_bus: true,
+            capabilities: vec!["SYS_ADMIN".into(), "NET_RAW".into(), "!NET_RAW".into()],
+            engine_args: vec!["--volume=/:/host".into()],
+            mounts: vec!["~/.gitconfig:.gitconfig:ro".parse().unwrap()],
+            env: [("PROJECT".into(), "${HOME}/project".into())].into(),
+            selinux: SELinuxMode::Private,
+            ..Default::default()
+        };
+
+        let findings = audit_config(&config)
+            .into_iter()
+            .map(|x| (x.level, x.field))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            findings,
+            vec![
+                (RiskLevel::Critical, "session_bus"),
+                (RiskLevel::Critical, "capabilities"),
+                (RiskLevel::Critical, "engine_args"),
+                (RiskLevel::Low, "mounts"),
+                (RiskLevel::Low, "env"),
+            ]
+        );
+
+        assert!(print_audit(&config, Some(RiskLevel::Critical)).is_ok());
+        assert!(print_audit(&config, Some(RiskLevel::High)).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! Upgrading config files to the latest version while keeping the formatting
+
+use crate::cli::{CmdConfigMigrateArgs, ConfigArg};
+use crate::config::{Config, ConfigFile, CONDITIONS_KEY};
+use crate::prelude::*;
+use std::path::PathBuf;
+use toml_edit::{DocumentMut, Item};
+
+/// Converts item to a plain value so it can be compared regardless of formatting
+fn item_value(item: &Item) -> Option<toml::Value> {
+    let mut doc = DocumentMut::new();
+    doc.insert("value", item.clone());
+
+    toml::from_str::<toml::Table>(&doc.to_string())
+        .ok()?
+        .remove("value")
+}
+
+/// Converts plain value into an item
+fn value_item(value: toml::Value) -> Result<Item> {
+    let mut table = toml::Table::new();
+    table.insert("value".into(), value);
+
+    let doc = toml::to_string(&table)?.parse::<DocumentMut>()?;
+    Ok(doc["value"].clone())
+}
+
+/// Replaces the item with new one, keeping its formatting if possible
+fn replace_item(old: &mut Item, new: &Item) {
+    let mut new = new.clone();
+
+    // keep the value in the same place, tables would be moved to the end of the file
+    if let Item::Value(old_value) = old {
+        new = match new.into_value() {
+            Ok(mut value) => {
+                *value.decor_mut() = old_value.decor().clone();
+                Item::Value(value)
+            }
+            Err(x) => x,
+        };
+    }
+
+    *old = new;
+}
+
+/// Migrates conditional section written in the syntax of the base config, returns migrated values
+/// of the keys set in the section
+fn migrate_section(base: &toml::Table, section: &toml::Table) -> Result<toml::Table> {
+    // older versions have no tables so the section values just replace the base ones
+    let mut merged = base.clone();
+    merged.extend(section.clone());
+
+    let config: Config = toml::Value::Table(merged)
+        .try_into::<ConfigFile>()?
+        .try_into()?;
+    let mut migrated =
+        toml::Value::try_from(ConfigFile::latest(config))?.try_into::<toml::Table>()?;
+
+    Ok(section
+        .keys()
+        .filter_map(|x| Some((x.clone(), migrated.remove(x)?)))
+        .collect())
+}
+
+/// Parses the base config with a single conditional section forced on
+fn config_with_section(
+    base: &toml::Table,
+    kind: &str,
+    condition: &str,
+    section: &toml::Table,
+) -> Result<Config> {
+    let mut table = base.clone();
+    table.insert(
+        CONDITIONS_KEY.into(),
+        toml::Value::Table(toml::Table::from_iter([(
+            kind.to_string(),
+            toml::Value::Table(toml::Table::from_iter([(
+                condition.to_string(),
+                toml::Value::Table(section.clone()),
+            )])),
+        )])),
+    );
+
+    ConfigFile::config_from_str_all_conditions(&toml::to_string(&table)?)
+}
+
+/// Migrates config to the latest version, returns `None` if it already is the latest version
+///
+/// Only values that changed are replaced so comments and ordering are kept where possible
+fn migrate_document(input: &str) -> Result<Option<String>> {
+    // parse it first so any errors are reported same as in start
+    ConfigFile::config_from_str(input)?;
+
+    // conditional sections are only checked when they match, so check all of them
+    let original = ConfigFile::config_from_str_all_conditions(input)
+        .context("while applying all conditional sections")?;
+
+    let mut doc = input.parse::<DocumentMut>()?;
+    if doc.get("version").and_then(|x| x.as_str()) == Some(&Config::VERSION.to_string()) {
+        return Ok(None);
+    }
+
+    // base config is migrated first, otherwise matching sections would be merged into it
+    let conditions = doc.remove(CONDITIONS_KEY);
+    let old_base = toml::from_str::<toml::Table>(&doc.to_string())?;
+
+    let config = ConfigFile::config_from_str(&doc.to_string())?;
+
+    let migrated = toml::to_string(&ConfigFile::latest(config.clone()))?.parse::<DocumentMut>()?;
+    let defaults =
+        toml::to_string(&ConfigFile::latest(Config::default()))?.parse::<DocumentMut>()?;
+
+    let keys = doc.iter().map(|(x, _)| x.to_string()).collect::<Vec<_>>();
+    for key in keys {
+        let Some(new) = migrated.get(&key) else {
+            // option was removed
+            doc.remove(&key);
+            continue;
+        };
+
+        let old = doc.get_mut(&key).unwrap();
+        if item_value(old) != item_value(new) {
+            replace_item(old, new);
+        }
+    }
+
+    // add new options only if they are not the default
+    for (key, item) in migrated.iter() {
+        if doc.contains_key(key) || defaults.get(key).map(item_value) == Some(item_value(item)) {
+            continue;
+        }
+
+        doc.insert(key, item.clone());
+    }
+
+    // just to be safe make sure nothing was lost along the way
+    let check = ConfigFile::config_from_str(&doc.to_string())
+        .context("Migrated config is invalid, please report this as a bug")?;
+    if check != config {
+        return Err(anyhow!(
+            "Migrated config differs from the original, please report this as a bug"
+        ));
+    }
+
+    if let Some(mut conditions) = conditions {
+        let new_base = toml::from_str::<toml::Table>(&doc.to_string())?;
+
+        for (kind, sections) in conditions
+            .as_table_like_mut()
+            .into_iter()
+            .flat_map(|x| x.iter_mut())
+        {
+            for (condition, section) in sections
+                .as_table_like_mut()
+                .into_iter()
+                .flat_map(|x| x.iter_mut())
+            {
+                let label = format!(
+                    "{}.{}.{}",
+                    CONDITIONS_KEY,
+                    kind.get(),
+                    condition.display_repr()
+                );
+                let Some(toml::Value::Table(old_section)) = item_value(section) else {
+                    continue;
+                };
+
+                let new_section = migrate_section(&old_base, &old_section)
+                    .with_context(|| format!("while migrating {:?}", label))?;
+
+                // tables are merged with the base config instead of replacing it
+                if config_with_section(&old_base, kind.get(), condition.get(), &old_section)?
+                    != config_with_section(&new_base, kind.get(), condition.get(), &new_section)?
+                {
+                    return Err(anyhow!(
+                        "Section {:?} cannot be migrated automatically as tables are merged with the base config in version {}, please migrate it manually",
+                        label,
+                        Config::VERSION
+                    ));
+                }
+
+                let Some(section) = section.as_table_like_mut() else {
+                    continue;
+                };
+
+                let keys = section
+                    .iter()
+                    .map(|(x, _)| x.to_string())
+                    .collect::<Vec<_>>();
+                for key in keys {
+                    let Some(new) = new_section.get(&key) else {
+                        section.remove(&key);
+                        continue;
+                    };
+
+                    let old = section.get_mut(&key).unwrap();
+                    if item_value(old).as_ref() != Some(new) {
+                        replace_item(old, &value_item(new.clone())?);
+                    }
+                }
+            }
+        }
+
+        doc.insert(CONDITIONS_KEY, conditions);
+    }
+
+    let output = doc.to_string();
+
+    // every section has to work after migration
+    let check = ConfigFile::config_from_str_all_conditions(&output)
+        .context("Migrated config is invalid, please report this as a bug")?;
+    if check != original {
+        return Err(anyhow!(
+            "Migrated config differs from the original, please report this as a bug"
+        ));
+    }
+
+    Ok(Some(output))
+}
+
+pub fn migrate_command(ctx: &Context, cli_args: CmdConfigMigrateArgs) -> Result<()> {
+    if !cli_args.check && !cli_args.in_place && cli_args.configs.len() > 1 {
+        return Err(anyhow!(
+            "Printing multiple configs is not supported, use --check or --in-place"
+        ));
+    }
+
+    let mut outdated: usize = 0;
+
+    for config in cli_args.configs {
+        let path: PathBuf = match config {
+            ConfigArg::File(x) => x,
+            ConfigArg::Config(x) => ctx.find_config(&x)?.path.unwrap(),
+            ConfigArg::Image(x) => {
+                return Err(anyhow!("Cannot migrate config inside image {:?}", x));
+            }
+        };
+
+        let input = std::fs::read_to_string(&path)
+            .with_context(|| format!("while reading config file {:?}", path))?;
+
+        let migrated = migrate_document(&input)
+            .with_context(|| format!("while migrating config file {:?}", path))?;
+
+        if cli_args.check {
+            match migrated {
+                Some(_) => {
+                    println!("{:?} is outdated", path);
+                    outdated += 1;
+                }
+                None => println!("{:?} is up to date", path),
+            }
+        } else if cli_args.in_place {
+            let Some(migrated) = migrated else {
+                println!("{:?} is up to date", path);
+                continue;
+            };
+
+            if ctx.dry_run {
+                println!("Would migrate {:?} to version {}", path, Config::VERSION);
+                continue;
+            }
+
+            std::fs::write(&path, migrated)
+                .with_context(|| format!("while writing config file {:?}", path))?;
+
+            println!("Migrated {:?} to version {}", path, Config::VERSION);
+        } else {
+            print!("{}", migrated.unwrap_or(input));
+        }
+    }
+
+    if outdated > 0 {
+        return Err(anyhow!(
+            "{} config(s) need to be migrated to version {}",
+            outdated,
+            Config::VERSION
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_v1() {
+        let input = r#"# my dev container
+version = "1"
+image = "fedora" # pinned elsewhere
+
+# forwarded for the dev server
+ports = [ [ 8080, 8080 ], [ 80, 8000 ] ]
+env = [ [ "EDITOR", "nvim" ] ]
+network = true
+"#;
+
+        let output = migrate_document(input).unwrap().unwrap();
+
+        assert_eq!(
+            output,
+            r#"# my dev container
+version = "2"
+image = "fedora" # pinned elsewhere
+
+# forwarded for the dev server
+ports = [8080, "80:8000"]
+env = { EDITOR = "nvim" }
+network = true
+ignore_missing_env = true
+"#
+        );
+
+        assert_eq!(migrate_document(&output).unwrap(), None);
+    }
+
+    #[test]
+    fn migrate_keeps_conditions() {
+        let input = r#"version = "1"
+image = "fedora"
+
+# only when testing
+[when.env.ARCAM_TEST]
+network = true
+"#;
+
+        let output = migrate_document(input).unwrap().unwrap();
+
+        assert_eq!(
+            output,
+            r#"version = "2"
+image = "fedora"
+ignore_missing_env = true
+
+# only when testing
+[when.env.ARCAM_TEST]
+network = true
+"#
+        );
+    }
+
+    #[test]
+    fn migrate_conditions_v1() {
+        let input = r#"version = "1"
+image = "fedora"
+ports = [ [ 8080, 8080 ] ]
+
+[when.env.ARCAM_TEST]
+env = [ [ "EDITOR", "nvim" ] ] # editor for tests
+ports = [ [ 80, 8000 ] ]
+on_init_pre = "echo $HOME"
+"#;
+
+        let output = migrate_document(input).unwrap().unwrap();
+
+        assert_eq!(
+            output,
+            r#"version = "2"
+image = "fedora"
+ports = [8080]
+ignore_missing_env = true
+
+[when.env.ARCAM_TEST]
+env = { EDITOR = "nvim" } # editor for tests
+ports = ["80:8000"]
+on_init_pre = "echo $$HOME"
+"#
+        );
+
+        let config = ConfigFile::config_from_str_all_conditions(&output).unwrap();
+        assert_eq!(config.env["EDITOR"], "nvim");
+        assert_eq!(config.on_init_pre.as_deref(), Some("echo $$HOME"));
+
+        // base env would be kept by the merge in version 2
+        let input = r#"version = "1"
+image = "fedora"
+env = [ [ "TERM", "xterm" ] ]
+
+[when.env.ARCAM_TEST]
+env = [ [ "EDITOR", "nvim" ] ]
+"#;
+        let err = migrate_document(input).unwrap_err().to_string();
+        assert!(err.contains("migrate it manually"), "{}", err);
+
+        // sections with old syntax in a new config are reported
+        let input = r#"version = "2"
+image = "fedora"
+
+[when.env.ARCAM_TEST]
+env = [ [ "EDITOR", "nvim" ] ]
+"#;
+        assert!(migrate_document(input).is_err());
+    }
+}
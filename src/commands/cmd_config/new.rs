@@ -0,0 +1,356 @@
+//! Creating new configs interactively or from start args
+
+use crate::cli::{CmdConfigNewArgs, CmdStartArgs, ConfigArg};
+use crate::config::{Config, ConfigFile, Mount, PortMapping, SecretSource};
+use crate::prelude::*;
+use clap::Parser;
+use code_docs::DocumentedStruct;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use toml_edit::DocumentMut;
+
+/// File name used for config in the project directory
+const PROJECT_CONFIG: &str = ".arcam.toml";
+
+/// Used to parse start args on their own
+#[derive(Parser)]
+struct StartArgsWrapper {
+    #[command(flatten)]
+    args: CmdStartArgs,
+}
+
+/// Converts start args into a config, relative paths are resolved against `cwd`
+fn config_from_args(args: CmdStartArgs, cwd: &Path) -> Result<Config> {
+    let ConfigArg::Image(image) = args.config else {
+        return Err(anyhow!(
+            "Start args must use an image to be converted, not a config"
+        ));
+    };
+
+    if args.name.is_some() {
+        log::warn!("Container name cannot be set in a config, ignoring it");
+    }
+
+    // values from cli are literal while config values are expanded
+    let escape = |x: &str| x.replace('$', "$$");
+
+    let mut env = BTreeMap::<String, String>::new();
+    for i in args.env {
+        match i.split_once('=') {
+            Some((k, v)) => env.insert(k.to_string(), escape(v)),
+            // without value it is passed from host, which is the same as expanding it
+            None => env.insert(i.clone(), format!("${{{}:-}}", i)),
+        };
+    }
+
+    let join_commands = |x: Vec<String>| (!x.is_empty()).then(|| escape(&x.join("\n")));
+
+    Ok(Config {
+        image: escape(&image),
+        // in config it would be relative to the config
+        skel: args
+            .skel
+            .iter()
+            .map(|x| escape(&cwd.join(x).to_string_lossy()))
+            .collect(),
+        skel_mode: args.skel_mode.unwrap_or_default(),
+        shell: args.shell,
+        workspace: args.workspace.unwrap_or_default(),
+        network: args.network.unwrap_or_default(),
+        pipewire: args.pipewire.unwrap_or_default(),
+        pulseaudio: args.pulseaudio.unwrap_or_default(),
+        wayland: args.wayland.unwrap_or_default(),
+        x11: args.x11.unwrap_or_default(),
+        ssh_agent: args.ssh_agent.unwrap_or_default(),
+        session_bus: args.session_bus.unwrap_or_default(),
+        cache_volume: args.cache_volume.unwrap_or_default(),
+        packages: args.packages,
+        // in config it would be relative to the config
+        mounts: args
+            .mount
+            .into_iter()
+            .map(|x| Mount {
+                source: escape(&cwd.join(&x.source).to_string_lossy()),
+                destination: x.destination.as_deref().map(escape),
+                ..x
+            })
+            .collect(),
+        on_init_pre: join_commands(args.on_init_pre),
+        on_init_post: join_commands(args.on_init_post),
+        ports: args
+            .ports
+            .into_iter()
+            .map(|(container, host)| PortMapping::new(container, host))
+            .collect(),
+        env,
+        // in config it would be relative to the config
+        env_file: args
+            .env_file
+            .iter()
+            .map(|x| escape(&cwd.join(x).to_string_lossy()))
+            .collect(),
+        env_passthrough: args.env_passthrough,
+        secrets: args
+            .secrets
+            .into_iter()
+            .map(|(name, source)| match source {
+                SecretSource::File(x) => (
+                    name,
+                    SecretSource::File(escape(&cwd.join(x).to_string_lossy())),
+                ),
+                x => (name, x),
+            })
+            .collect(),
+        capabilities: args.capabilities,
+        no_new_privileges: args.no_new_privileges.unwrap_or_default(),
+        // in config it would be relative to the config
+        seccomp_profile: args
+            .seccomp_profile
+            .map(|x| escape(&cwd.join(x).to_string_lossy())),
+        deny_syscalls: args.deny_syscalls,
+        selinux: args.selinux.unwrap_or_default(),
+        privilege: args.privilege.unwrap_or_default(),
+        engine_args: args.engine_args.iter().map(|x| escape(x)).collect(),
+        ..Default::default()
+    })
+}
+
+/// Parses start args, the `arcam start` prefix is optional so command line can be pasted as is
+fn parse_start_args(args: &[String]) -> Result<CmdStartArgs> {
+    let mut args = args.iter().map(String::as_str).peekable();
+    args.next_if_eq(&crate::APP_NAME);
+    args.next_if_eq(&"start");
+
+    Ok(StartArgsWrapper::try_parse_from(std::iter::once("start").chain(args))?.args)
+}
+
+/// Asks user for the config options
+fn config_interactive() -> Result<Config> {
+    // stdin closing should not loop forever
+    let input = |prompt: &str| -> Result<String> {
+        crate::prompt_input(prompt).ok_or_else(|| anyhow!("Input closed unexpectedly"))
+    };
+
+    let mut config = Config::default();
+
+    while config.image.is_empty() {
+        config.image = input("Image (ex. docker.io/library/debian)")?;
+    }
+
+    let shell = input("Shell (empty for image default)")?;
+    config.shell = (!shell.is_empty()).then_some(shell);
+
+    config.network = crate::prompt("Allow network access?");
+    config.pipewire = crate::prompt("Pass through pipewire?");
+    config.pulseaudio = crate::prompt("Pass through pulseaudio?");
+    config.wayland = crate::prompt("Pass through wayland (allows clipboard access)?");
+    config.x11 = crate::prompt("Pass through X11 (allows clipboard access)?");
+    config.ssh_agent = crate::prompt("Pass through ssh-agent?");
+    config.session_bus =
+        crate::prompt("Pass through session bus (allows command execution on host)?");
+
+    'ports: loop {
+        config.ports.clear();
+
+        for i in
+            input("Ports to publish (PORT[:HOST_PORT] separated by spaces)")?.split_whitespace()
+        {
+            match i.parse::<PortMapping>() {
+                Ok(x) => config.ports.push(x),
+                Err(err) => {
+                    println!("{}", err);
+                    continue 'ports;
+                }
+            }
+        }
+
+        break;
+    }
+
+    'persist: loop {
+        config.persist.clear();
+
+        for i in input("Persistent volumes (NAME=PATH separated by spaces)")?.split_whitespace() {
+            let Some((name, path)) = i.split_once('=') else {
+                println!("Invalid volume {:?}, expected NAME=PATH", i);
+                continue 'persist;
+            };
+
+            config.persist.insert(name.to_string(), path.to_string());
+        }
+
+        break;
+    }
+
+    let on_init_pre = input("Command to run on init before other scripts (empty for none)")?;
+    config.on_init_pre = (!on_init_pre.is_empty()).then_some(on_init_pre);
+
+    let on_init_post = input("Command to run on init after other scripts (empty for none)")?;
+    config.on_init_post = (!on_init_post.is_empty()).then_some(on_init_post);
+
+    Ok(config)
+}
+
+/// Serializes config with only the non-default options, each one commented with its docs
+pub(super) fn config_to_commented_toml(config: &Config) -> Result<String> {
+    let mut doc = toml::to_string(&ConfigFile::latest(config.clone()))?.parse::<DocumentMut>()?;
+    let defaults =
+        toml::to_string(&ConfigFile::latest(Config::default()))?.parse::<DocumentMut>()?;
+
+    let docs = Config::field_names()
+        .into_iter()
+        .zip(Config::field_docs())
+        .collect::<BTreeMap<_, _>>();
+
+    let keys = doc.iter().map(|(x, _)| x.to_string()).collect::<Vec<_>>();
+    for key in keys {
+        // version and image are required
+        if key == "version" {
+            continue;
+        }
+
+        let is_default = key != "image"
+            && defaults.get(&key).map(|x| x.to_string()) == doc.get(&key).map(|x| x.to_string());
+
+        if is_default {
+            doc.remove(&key);
+            continue;
+        }
+
+        // only use the first paragraph of docs
+        let comment = docs
+            .get(key.as_str())
+            .map(|x| {
+                x.iter()
+                    .take_while(|x| !x.trim().is_empty())
+                    .map(|x| format!("#{}\n", x))
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+
+        match doc.get_mut(&key).unwrap() {
+            toml_edit::Item::Table(table) => {
+                table.decor_mut().set_prefix(format!("\n{}", comment));
+            }
+            _ => {
+                if let Some(mut key) = doc.key_mut(&key) {
+                    key.leaf_decor_mut().set_prefix(format!("\n{}", comment));
+                }
+            }
+        }
+    }
+
+    let output = format!(
+        "# Generated by {}, see `{0} config --options` for all options\n{}",
+        crate::APP_NAME,
+        doc
+    );
+
+    // make sure it is valid
+    let parsed = ConfigFile::config_from_str(&output).context("Generated config is invalid")?;
+    if parsed != *config {
+        return Err(anyhow!(
+            "Generated config differs, please report this as a bug"
+        ));
+    }
+
+    Ok(output)
+}
+
+pub fn new_command(ctx: &Context, cli_args: CmdConfigNewArgs) -> Result<()> {
+    let path = match &cli_args.name {
+        Some(name) => {
+            if name.contains('/') || name.starts_with('.') {
+                return Err(anyhow!("Invalid config name {:?}", name));
+            }
+
+            ctx.config_dir().join(format!("{}.toml", name))
+        }
+        None => ctx.cwd.join(PROJECT_CONFIG),
+    };
+
+    if path.exists() && !cli_args.force {
+        return Err(anyhow!(
+            "Config {:?} already exists, use --force to overwrite it",
+            path
+        ));
+    }
+
+    let config = if cli_args.from_args {
+        config_from_args(parse_start_args(&cli_args.start_args)?, &ctx.cwd)?
+    } else {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow!(
+                "Cannot ask for options as stdin is not a terminal, use --from-args instead"
+            ));
+        }
+
+        config_interactive()?
+    };
+
+    let output = config_to_commented_toml(&config)?;
+
+    if ctx.dry_run {
+        println!("Would write config {:?}\n{}", path, output);
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+
+    std::fs::write(&path, output).with_context(|| format!("Failed to write config {:?}", path))?;
+
+    println!("Config written to {:?}", path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_new_from_args() {
+        let args = [
+            "arcam",
+            "start",
+            "--network",
+            "--port=80:8080",
+            "-e",
+            "EDITOR=nvim",
+            "-e",
+            "TERM",
+            "--on-init-pre",
+            "echo hi",
+            "fedora",
+            "--",
+            "--device=/dev/kvm",
+        ]
+        .map(String::from);
+
+        let config = config_from_args(parse_start_args(&args).unwrap(), Path::new("/")).unwrap();
+        assert_eq!(config.image, "fedora");
+        assert!(config.network);
+        assert_eq!(
+            config.env.get("TERM").map(String::as_str),
+            Some("${TERM:-}")
+        );
+        assert_eq!(config.engine_args, vec!["--device=/dev/kvm".to_string()]);
+
+        let output = config_to_commented_toml(&config).unwrap();
+        assert!(
+            output.contains("\n# Set network access\nnetwork = true\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("ports = [\"80:8080\"]"), "{}", output);
+        assert!(!output.contains("pipewire"), "{}", output);
+
+        assert!(parse_start_args(&["@dev".to_string()])
+            .and_then(|x| config_from_args(x, Path::new("/")))
+            .is_err());
+    }
+}
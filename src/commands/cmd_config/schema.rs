@@ -0,0 +1,337 @@
+//! JSON Schema of the latest config version generated from the config docs
+
+use crate::config::{Config, PrivilegeMode, SELinuxMode, SkelMode, WorkspaceMode, CONDITIONS_KEY};
+use crate::prelude::*;
+use code_docs::DocumentedStruct;
+use serde_json::{json, Map, Value};
+
+/// Schema for enum that is both a clap value and serialized in lowercase
+fn enum_schema<T: clap::ValueEnum>() -> Value {
+    let variants = T::value_variants()
+        .iter()
+        .filter_map(|x| x.to_possible_value())
+        .collect::<Vec<_>>();
+
+    let description = variants
+        .iter()
+        .map(|x| match x.get_help() {
+            Some(help) => format!("{}: {}", x.get_name(), help),
+            None => x.get_name().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    json!({
+        "type": "string",
+        "enum": variants.iter().map(|x| x.get_name()).collect::<Vec<_>>(),
+        "description": description,
+    })
+}
+
+/// Splits by comma ignoring commas nested in generics or tuples
+fn split_top_level(input: &str) -> Vec<&str> {
+    let mut parts: Vec<&str> = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (index, c) in input.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&input[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&input[start..]);
+    parts.into_iter().filter(|x| !x.is_empty()).collect()
+}
+
+/// Converts rust type (as written in the struct) into JSON Schema
+fn type_schema(r#type: &str) -> Result<Value> {
+    // stringify may add spaces around the generics
+    let r#type = r#type
+        .chars()
+        .filter(|x| !x.is_whitespace())
+        .collect::<String>();
+
+    // returns inner type of a generic type
+    let generic = |name: &str| -> Option<&str> {
+        r#type
+            .strip_prefix(name)
+            .and_then(|x| x.strip_prefix('<'))
+            .and_then(|x| x.strip_suffix('>'))
+    };
+
+    if let Some(inner) = generic("Option") {
+        // optionality is handled by required fields
+        return type_schema(inner);
+    }
+
+    if let Some(inner) = generic("Vec") {
+        return Ok(json!({
+            "type": "array",
+            "items": type_schema(inner)?,
+        }));
+    }
+
+    if let Some(inner) = generic("BTreeMap") {
+        let [key, value] = split_top_level(inner)[..] else {
+            return Err(anyhow!("Invalid map type {:?}", r#type));
+        };
+
+        if key != "String" {
+            return Err(anyhow!("Map keys must be strings in {:?}", r#type));
+        }
+
+        return Ok(json!({
+            "type": "object",
+            "additionalProperties": type_schema(value)?,
+        }));
+    }
+
+    if let Some(inner) = r#type.strip_prefix('(').and_then(|x| x.strip_suffix(')')) {
+        let items = split_top_level(inner)
+            .into_iter()
+            .map(type_schema)
+            .collect::<Result<Vec<_>>>()?;
+
+        // draft-07 tuple validation
+        return Ok(json!({
+            "type": "array",
+            "items": items,
+            "minItems": items.len(),
+            "maxItems": items.len(),
+        }));
+    }
+
+    Ok(match r#type.as_str() {
+        "bool" => json!({ "type": "boolean" }),
+        "String" | "PathBuf" => json!({ "type": "string" }),
+        "u8" | "u16" | "u32" | "u64" | "usize" => json!({ "type": "integer", "minimum": 0 }),
+        "i8" | "i16" | "i32" | "i64" | "isize" => json!({ "type": "integer" }),
+        "PortMapping" => json!({
+            "oneOf": [
+                { "type": "integer", "minimum": 0 },
+                { "type": "string", "pattern": "^[0-9]+(:[0-9]+)?$" },
+                // variables are expanded before it is parsed
+                { "type": "string", "pattern": "\\$", "description": "Mapping containing variables" },
+            ],
+        }),
+        "SecretSource" => json!({
+            "type": "object",
+            "additionalProperties": false,
+            "minProperties": 1,
+            "maxProperties": 1,
+            "properties": {
+                "file": { "type": "string", "description": "Contents of a file" },
+                "command": { "type": "string", "description": "Output of a command ran using /bin/sh on the host" },
+                "env": { "type": "string", "description": "Value of a host environment variable" },
+            },
+        }),
+        "Mount" => json!({
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["source"],
+            "properties": {
+                "source": { "type": "string", "description": "Path on the host, file or directory" },
+                "destination": { "type": "string", "description": "Absolute path or relative to the container home, without it the directory is mounted inside the workspace" },
+                "alias": { "type": "string", "description": "Directory name inside the workspace used instead of the source basename" },
+                "readonly": { "type": "boolean" },
+                "noexec": { "type": "boolean" },
+            },
+        }),
+        "SkelList" => json!({
+            "oneOf": [
+                { "type": "string" },
+                { "type": "array", "items": { "type": "string" } },
+            ],
+        }),
+        "SELinuxMode" => enum_schema::<SELinuxMode>(),
+        "PrivilegeMode" => enum_schema::<PrivilegeMode>(),
+        "SkelMode" => enum_schema::<SkelMode>(),
+        "WorkspaceMode" => enum_schema::<WorkspaceMode>(),
+        x => return Err(anyhow!("Cannot convert type {:?} into JSON Schema", x)),
+    })
+}
+
+/// Joins doc lines into a description
+fn docs_to_description(docs: &[&str]) -> String {
+    docs.iter()
+        .map(|x| x.strip_prefix(' ').unwrap_or(x))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Generates JSON Schema for the latest config version
+pub fn config_schema() -> Result<Value> {
+    let defaults = serde_json::to_value(Config::default())?;
+    let Value::Object(defaults) = defaults else {
+        unreachable!("Config is not serialized as an object");
+    };
+
+    let mut properties = Map::new();
+    let mut required = vec!["version".to_string()];
+
+    properties.insert(
+        "version".into(),
+        json!({
+            "description": "Config schema version",
+            "const": Config::VERSION.to_string(),
+        }),
+    );
+
+    let iter = Config::field_names()
+        .into_iter()
+        .zip(Config::field_types())
+        .zip(Config::field_docs());
+
+    for ((name, r#type), docs) in iter {
+        if docs.join("\n").contains("@skip") {
+            continue;
+        }
+
+        let mut schema = type_schema(r#type)
+            .with_context(|| format!("while generating schema for field {:?}", name))?;
+
+        // field is required if config cannot be parsed without it
+        let is_required = {
+            let mut without = defaults.clone();
+            without.remove(name);
+            serde_json::from_value::<Config>(Value::Object(without)).is_err()
+        };
+
+        if is_required {
+            required.push(name.to_string());
+        } else if let Some(default) = defaults.get(name).filter(|x| !x.is_null()) {
+            schema["default"] = default.clone();
+        }
+
+        // put the field docs before enum variant docs
+        let description = match schema.get("description").and_then(|x| x.as_str()) {
+            Some(variants) => format!("{}\n\n{}", docs_to_description(&docs), variants),
+            None => docs_to_description(&docs),
+        };
+        schema["description"] = description.into();
+
+        properties.insert(name.to_string(), schema);
+    }
+
+    // conditional sections can set any option except the version
+    let section = json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": properties
+            .iter()
+            .filter(|(k, _)| *k != "version")
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Map<_, _>>(),
+    });
+    let sections = |description: &str| {
+        json!({
+            "type": "object",
+            "description": description,
+            "additionalProperties": section,
+        })
+    };
+
+    properties.insert(
+        CONDITIONS_KEY.into(),
+        json!({
+            "type": "object",
+            "description": "Conditional sections merged over the config, applied in order env, hostname, exists",
+            "additionalProperties": false,
+            "properties": {
+                "env": sections("Applied if variable is set and not empty, or matches pattern with `NAME=PATTERN`"),
+                "hostname": sections("Applied if hostname matches the pattern"),
+                "exists": sections("Applied if the path exists, environ vars are expanded"),
+            },
+        }),
+    );
+
+    Ok(json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": format!("{} config", crate::APP_NAME),
+        "description": docs_to_description(&Config::struct_docs()),
+        "type": "object",
+        "additionalProperties": false,
+        "required": required,
+        "properties": properties,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_types() {
+        assert_eq!(
+            type_schema("Vec < (u32, u32) >").unwrap(),
+            json!({
+                "type": "array",
+                "items": {
+                    "type": "array",
+                    "items": [
+                        { "type": "integer", "minimum": 0 },
+                        { "type": "integer", "minimum": 0 },
+                    ],
+                    "minItems": 2,
+                    "maxItems": 2,
+                },
+            })
+        );
+        assert_eq!(
+            type_schema("Option<String>").unwrap(),
+            json!({ "type": "string" })
+        );
+        assert_eq!(
+            type_schema("BTreeMap<String, String>").unwrap(),
+            json!({ "type": "object", "additionalProperties": { "type": "string" } })
+        );
+        assert!(type_schema("HashSet<String>").is_err());
+    }
+
+    #[test]
+    fn schema_config() {
+        let schema = config_schema().unwrap();
+
+        assert_eq!(schema["required"], json!(["version", "image"]));
+        assert_eq!(
+            schema["properties"]["version"]["const"],
+            json!(Config::VERSION.to_string())
+        );
+        assert_eq!(schema["properties"]["network"]["default"], json!(false));
+        assert_eq!(
+            schema["properties"]["workspace"]["enum"],
+            json!(["rw", "ro", "overlay"])
+        );
+        assert!(schema["properties"].get("path").is_none());
+        assert_eq!(
+            schema["properties"]["when"]["properties"]["env"]["additionalProperties"]["properties"]
+                ["network"]["default"],
+            json!(false)
+        );
+
+        // exactly one branch has to match for oneOf
+        let port = &schema["properties"]["ports"]["items"]["oneOf"];
+        let matching = |value: &str| {
+            port.as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|x| x["pattern"].as_str())
+                .filter(|x| regex::Regex::new(x).unwrap().is_match(value))
+                .count()
+        };
+
+        assert_eq!(matching("80:8080"), 1);
+        assert_eq!(matching("${PORT:-80}"), 1);
+        assert_eq!(matching("8080:${PORT}"), 1);
+        assert_eq!(matching("http"), 0);
+    }
+}
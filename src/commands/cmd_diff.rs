@@ -0,0 +1,401 @@
+use crate::cli;
+use crate::command_extensions::*;
+use crate::prelude::*;
+use std::fmt::Display;
+use std::fs;
+use std::os::unix::fs::{symlink, FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// Prefix used for whiteouts by overlay implementations that do not use character devices
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Marks directory as opaque, the lower directory contents are hidden
+const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+
+/// Single change in the overlay, path is relative to the project directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    Modified(PathBuf),
+    Deleted(PathBuf),
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Added(x) | Self::Modified(x) | Self::Deleted(x) => x,
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added(x) => write!(f, "A {}", x.display()),
+            Self::Modified(x) => write!(f, "M {}", x.display()),
+            Self::Deleted(x) => write!(f, "D {}", x.display()),
+        }
+    }
+}
+
+/// Compare two files by content and permissions
+fn is_same_file(a: &Path, b: &Path) -> Result<bool> {
+    let (meta_a, meta_b) = (a.symlink_metadata()?, b.symlink_metadata()?);
+
+    if meta_a.file_type() != meta_b.file_type() || meta_a.mode() != meta_b.mode() {
+        return Ok(false);
+    }
+
+    if meta_a.is_symlink() {
+        return Ok(a.read_link()? == b.read_link()?);
+    }
+
+    if meta_a.len() != meta_b.len() {
+        return Ok(false);
+    }
+
+    Ok(fs::read(a)? == fs::read(b)?)
+}
+
+/// Walks the overlay upper directory recursively and collects changes compared to the lower
+/// directory
+///
+/// Inside an `opaque` directory the lower contents are hidden, so anything not in upper is deleted
+fn collect_changes(
+    upper: &Path,
+    lower: &Path,
+    prefix: &Path,
+    opaque: bool,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let dir = upper.join(prefix);
+    let mut entries = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read overlay directory {:?}", dir))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // keep the output stable
+    entries.sort_by_key(|x| x.file_name());
+
+    // directory was deleted and recreated, its lower children are hidden even in subdirectories
+    let opaque = opaque || entries.iter().any(|x| x.file_name() == WHITEOUT_OPAQUE);
+
+    if opaque {
+        if let Ok(lower_entries) = fs::read_dir(lower.join(prefix)) {
+            let mut hidden = lower_entries
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .map(|x| x.file_name())
+                .filter(|x| !entries.iter().any(|y| &y.file_name() == x))
+                .collect::<Vec<_>>();
+            hidden.sort();
+
+            changes.extend(hidden.into_iter().map(|x| Change::Deleted(prefix.join(x))));
+        }
+    }
+
+    for entry in entries {
+        let name = entry.file_name();
+        let path = prefix.join(&name);
+        let file_type = entry.file_type()?;
+
+        if name == WHITEOUT_OPAQUE {
+            continue;
+        }
+
+        // whiteout using a prefix
+        if let Some(deleted) = name.to_str().and_then(|x| x.strip_prefix(WHITEOUT_PREFIX)) {
+            changes.push(Change::Deleted(prefix.join(deleted)));
+            continue;
+        }
+
+        // whiteout using a character device 0/0
+        if file_type.is_char_device() && entry.metadata()?.rdev() == 0 {
+            changes.push(Change::Deleted(path));
+            continue;
+        }
+
+        let lower_path = lower.join(&path);
+        let lower_exists = lower_path.symlink_metadata().is_ok();
+
+        if file_type.is_dir() {
+            let count = changes.len();
+
+            collect_changes(upper, lower, &path, opaque, changes)?;
+
+            // show new empty directories as they would be lost otherwise
+            if !lower_exists && count == changes.len() {
+                changes.push(Change::Added(path));
+            }
+        } else if !lower_exists {
+            changes.push(Change::Added(path));
+        } else if !is_same_file(&entry.path(), &lower_path)? {
+            // files are copied up even if only opened for writing so compare the content
+            changes.push(Change::Modified(path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the change from upper directory to the lower directory
+fn apply_change(change: &Change, upper: &Path, lower: &Path) -> Result<()> {
+    let source = upper.join(change.path());
+    let dest = lower.join(change.path());
+
+    // remove whatever is at destination first
+    if let Ok(meta) = dest.symlink_metadata() {
+        if meta.is_dir() && (matches!(change, Change::Deleted(_)) || !source.is_dir()) {
+            fs::remove_dir_all(&dest)?;
+        } else if !meta.is_dir() {
+            fs::remove_file(&dest)?;
+        }
+    }
+
+    if let Change::Deleted(_) = change {
+        return Ok(());
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let meta = source.symlink_metadata()?;
+    if meta.is_symlink() {
+        symlink(source.read_link()?, &dest)?;
+    } else if meta.is_dir() {
+        fs::create_dir_all(&dest)?;
+    } else {
+        // NOTE copy clones permissions as well
+        fs::copy(&source, &dest)?;
+    }
+
+    Ok(())
+}
+
+/// Removes the overlay directory, some files inside may be owned by subordinate ids so it is
+/// removed from inside the engine user namespace
+pub fn remove_overlay_dir(ctx: &Context, dir: &Path) -> Result<()> {
+    let mut cmd = ctx.engine.command();
+    cmd.args(["unshare", "rm", "-rf"]).arg(dir);
+
+    if ctx.dry_run {
+        cmd.log();
+    } else {
+        cmd.log_output_anyhow()?;
+    }
+
+    Ok(())
+}
+
+pub fn workspace_diff(ctx: Context, mut cli_args: cli::CmdDiffArgs) -> Result<()> {
+    // try to find container in current directory
+    if cli_args.name.is_empty() {
+        let containers = ctx.get_cwd_containers()?;
+        if containers.is_empty() {
+            return Err(anyhow!(
+                "Could not find a running container in current directory"
+            ));
+        }
+
+        cli_args.name = containers.first().unwrap().clone();
+    } else if !ctx.engine.container_exists(&cli_args.name)? {
+        return Err(anyhow!("Container {:?} does not exist", &cli_args.name));
+    }
+
+    let container_info = ctx.engine.inspect_containers(vec![&cli_args.name])?;
+    let container_info = container_info.first().unwrap();
+
+    // check if container is owned
+    let Some(host_dir) = container_info.labels.get(crate::CONTAINER_LABEL_HOST_DIR) else {
+        return Err(anyhow!(
+            "Container {:?} is not owned by {}",
+            &cli_args.name,
+            crate::APP_NAME
+        ));
+    };
+
+    let Some(overlay_dir) = container_info
+        .labels
+        .get(crate::CONTAINER_LABEL_OVERLAY_DIR)
+    else {
+        return Err(anyhow!(
+            "Container {:?} was not started with overlay workspace",
+            &cli_args.name
+        ));
+    };
+
+    let upper = Path::new(overlay_dir).join("upper");
+    let lower = Path::new(host_dir);
+
+    let mut changes: Vec<Change> = vec![];
+    collect_changes(&upper, lower, Path::new(""), false, &mut changes)?;
+
+    if cli_args.apply.is_empty() && !cli_args.apply_all {
+        if changes.is_empty() {
+            println!("No changes");
+        }
+
+        for change in &changes {
+            println!("{}", change);
+        }
+
+        return Ok(());
+    }
+
+    let mut selected: Vec<&Change> = vec![];
+    if cli_args.apply_all {
+        selected.extend(changes.iter());
+    } else {
+        for i in &cli_args.apply {
+            // allow absolute paths inside the project directory
+            let path = Path::new(i);
+            let path = path
+                .strip_prefix(lower)
+                .or(path.strip_prefix("./"))
+                .unwrap_or(path);
+
+            let matched = changes
+                .iter()
+                .filter(|x| x.path().starts_with(path))
+                .collect::<Vec<_>>();
+
+            if matched.is_empty() {
+                return Err(anyhow!("There are no changes at path {:?}", i));
+            }
+
+            selected.extend(matched);
+        }
+    }
+
+    for change in selected {
+        if ctx.dry_run {
+            println!("Would apply {}", change);
+            continue;
+        }
+
+        apply_change(change, &upper, lower)
+            .with_context(|| format!("Failed to apply change {:?}", change.path()))?;
+
+        println!("Applied {}", change);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_changes() -> Result<()> {
+        let upper = tempfile::tempdir()?;
+        let lower = tempfile::tempdir()?;
+
+        fs::write(lower.path().join("same.txt"), "same")?;
+        fs::write(lower.path().join("modified.txt"), "old")?;
+        fs::write(lower.path().join("deleted.txt"), "deleted")?;
+
+        fs::write(upper.path().join("same.txt"), "same")?;
+        fs::write(upper.path().join("modified.txt"), "new")?;
+        fs::write(upper.path().join(".wh.deleted.txt"), "")?;
+        fs::create_dir_all(upper.path().join("dir").join("empty"))?;
+        fs::write(upper.path().join("dir").join("added.txt"), "added")?;
+
+        let mut changes: Vec<Change> = vec![];
+        collect_changes(
+            upper.path(),
+            lower.path(),
+            Path::new(""),
+            false,
+            &mut changes,
+        )?;
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::Deleted("deleted.txt".into()),
+                Change::Added("dir/added.txt".into()),
+                Change::Added("dir/empty".into()),
+                Change::Modified("modified.txt".into()),
+            ]
+        );
+
+        for change in &changes {
+            apply_change(change, upper.path(), lower.path())?;
+        }
+
+        assert!(!lower.path().join("deleted.txt").exists());
+        assert!(lower.path().join("dir").join("empty").is_dir());
+        assert_eq!(
+            fs::read_to_string(lower.path().join("modified.txt"))?,
+            "new"
+        );
+        assert_eq!(
+            fs::read_to_string(lower.path().join("dir").join("added.txt"))?,
+            "added"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlay_changes_opaque() -> Result<()> {
+        let upper = tempfile::tempdir()?;
+        let lower = tempfile::tempdir()?;
+
+        // directory deleted and recreated inside the container
+        fs::create_dir_all(lower.path().join("dir").join("sub"))?;
+        fs::write(lower.path().join("dir").join("kept.txt"), "kept")?;
+        fs::write(lower.path().join("dir").join("stale.txt"), "stale")?;
+        fs::write(
+            lower.path().join("dir").join("sub").join("stale.txt"),
+            "stale",
+        )?;
+        fs::write(lower.path().join("untouched.txt"), "untouched")?;
+
+        fs::create_dir_all(upper.path().join("dir").join("sub"))?;
+        fs::write(upper.path().join("dir").join(WHITEOUT_OPAQUE), "")?;
+        fs::write(upper.path().join("dir").join("kept.txt"), "kept")?;
+        fs::write(upper.path().join("dir").join("sub").join("new.txt"), "new")?;
+
+        let mut changes: Vec<Change> = vec![];
+        collect_changes(
+            upper.path(),
+            lower.path(),
+            Path::new(""),
+            false,
+            &mut changes,
+        )?;
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::Deleted("dir/stale.txt".into()),
+                Change::Deleted("dir/sub/stale.txt".into()),
+                Change::Added("dir/sub/new.txt".into()),
+            ]
+        );
+
+        for change in &changes {
+            apply_change(change, upper.path(), lower.path())?;
+        }
+
+        assert!(!lower.path().join("dir").join("stale.txt").exists());
+        assert!(!lower
+            .path()
+            .join("dir")
+            .join("sub")
+            .join("stale.txt")
+            .exists());
+        assert!(lower.path().join("dir").join("kept.txt").exists());
+        assert!(lower
+            .path()
+            .join("dir")
+            .join("sub")
+            .join("new.txt")
+            .exists());
+        assert!(lower.path().join("untouched.txt").exists());
+
+        Ok(())
+    }
+}
@@ -32,10 +32,15 @@ pub fn container_exec(ctx: Context, mut cli_args: cli::CmdExecArgs) -> Result<()
         )));
     };
 
+    // start in the same directory if cwd is mounted in the container
+    let workdir = crate::container_workdir(&container_info.labels, &ctx.cwd)
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| ws_dir.to_string());
+
     let mut cmd = ctx.engine.command();
     cmd.args(["exec", "-it"]);
     cmd.args([
-        format!("--workdir={}", ws_dir),
+        format!("--workdir={}", workdir),
         format!("--user={}", ctx.user),
         format!(
             "--env=TERM={}",
@@ -1,50 +1,51 @@
 //! Contains all code that should run inside the container as the init
 
+mod init_log;
+mod privilege;
+pub mod skel;
+pub mod status;
+mod user;
+
 use crate::command_extensions::*;
+use crate::config::SkelMode;
 use crate::prelude::*;
 use crate::FULL_VERSION;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::os::unix::fs::{chown, lchown, symlink, PermissionsExt};
+use status::InitPhase;
+use std::os::unix::fs::{chown, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
-/// Walk recursively collecting files/symlinks into one vec, dirs into another
-fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>, dirs: &mut Vec<PathBuf>) {
-    let iter = fs::read_dir(dir).unwrap();
-    for entry in iter {
-        match entry {
-            Ok(x) => match x.file_type() {
-                Ok(t) => {
-                    // strip prefix
-                    let clean_path = x.path().strip_prefix("./").unwrap().to_path_buf();
-
-                    if t.is_dir() {
-                        dirs.push(clean_path.to_path_buf());
-                        walk_dir(x.path().as_path(), files, dirs);
-                    } else if t.is_file() || t.is_symlink() {
-                        // save paths of files and symlinks
-                        files.push(clean_path.to_path_buf());
-                    } else {
-                        // all other files are errors cause they should not be there
-                        eprintln!("Invalid file type at {:?}", clean_path);
-                    }
-                }
-                Err(x) => eprintln!("Could not determine file type: {}", x),
-            },
-            Err(x) => eprintln!("Error while reading directory: {}", x),
+/// How long to wait for the host to read the failed status before exiting
+const FAILED_STATUS_TIMEOUT_SECS: u32 = 60;
+
+/// XDG base directory variables set by start
+const XDG_HOME_VARS: [&str; 4] = [
+    "XDG_CONFIG_HOME",
+    "XDG_DATA_HOME",
+    "XDG_STATE_HOME",
+    "XDG_CACHE_HOME",
+];
+
+/// Creates directory and all missing parents inside home owned by the user, the directory itself
+/// is always chowned as it may be a volume created by root
+fn create_user_dir(path: &Path, home: &Path, uid: u32, gid: u32) -> Result<()> {
+    let mut missing = path
+        .ancestors()
+        .take_while(|x| *x != home && !x.exists())
+        .collect::<Vec<_>>();
+
+    fs::create_dir_all(path)?;
+
+    // parents outside of home are left alone
+    if path.starts_with(home) {
+        if !missing.contains(&path) {
+            missing.push(path);
         }
-    }
-}
-
-/// Clone permissions between two paths, specificially `mode`
-fn clone_perm(source: &Path, dest: &Path) -> Result<(), std::io::Error> {
-    let source_perm = source.symlink_metadata()?.permissions();
-    let mut dest_perm = dest.symlink_metadata()?.permissions();
 
-    dest_perm.set_mode(source_perm.mode());
-
-    fs::set_permissions(dest, dest_perm)?;
+        for dir in missing {
+            chown(dir, Some(uid), Some(gid))?;
+        }
+    }
 
     Ok(())
 }
@@ -62,7 +63,7 @@ fn make_executable(path: &Path) -> Result<(), std::io::Error> {
 }
 
 fn initialization() -> Result<()> {
-    println!("{} {}", env!("CARGO_BIN_NAME"), FULL_VERSION);
+    init_log::info(&format!("{} {}", env!("CARGO_BIN_NAME"), FULL_VERSION));
 
     let user = std::env::var("HOST_USER").context("HOST_USER is undefined")?;
     let uid = std::env::var("HOST_USER_UID").context("HOST_USER_UID is undefined")?;
@@ -83,43 +84,17 @@ fn initialization() -> Result<()> {
         }
     };
 
-    // setting up the user
-    let user_found = Command::new("getent")
-        .args(["passwd", &user])
-        .output()
-        .expect("Error executing getent")
-        .status
-        .success();
-
-    let cmd = if !user_found {
-        println!("Creating user {:?}", user);
-
-        Command::new("useradd")
-            .args([
-                "--shell",
-                shell,
-                "--home-dir",
-                &home,
-                "--uid",
-                &uid,
-                "--user-group",
-                "--no-create-home",
-                &user,
-            ])
-            .log_status_anyhow()?
-    } else {
-        println!("Modifying user {:?}", user);
-
-        Command::new("usermod")
-            .args(["--home", &home, "--shell", shell, &user])
-            .log_status_anyhow()?
-    };
+    status::phase(InitPhase::User, None)?;
 
-    if !cmd.success() {
-        return Err(anyhow!("Error while setting up the user"));
-    }
+    user::setup_user(&user::User {
+        name: &user,
+        uid: uid_u,
+        gid: gid_u,
+        home: &home,
+        shell,
+    })?;
 
-    println!("Setting up the user home");
+    init_log::info("Setting up the user home");
 
     // create the home directory if missing
     if !Path::new(&home).exists() {
@@ -131,9 +106,10 @@ fn initialization() -> Result<()> {
 
     // generate font cache just in case
     {
-        println!("Recreating font cache");
+        status::phase(InitPhase::FontCache, None)?;
+        init_log::info("Recreating font cache");
 
-        let cmd = Command::new("fc-cache").log_status();
+        let cmd = init_log::run_section("fc-cache", &mut Command::new("fc-cache"));
 
         match cmd {
             Ok(x) => {
@@ -142,48 +118,22 @@ fn initialization() -> Result<()> {
                 }
             }
             // some images may not have it so im just gonna ignore it
-            Err(_) => println!("Failed to execute fc-cache, ignoring error.."),
+            Err(_) => init_log::info("Failed to execute fc-cache, ignoring error.."),
         }
     }
 
-    let mut files: Vec<PathBuf> = vec![];
-    let mut dirs: Vec<PathBuf> = vec![];
+    status::phase(InitPhase::Skel, None)?;
 
-    // NOTE changing directory so i get './' paths and don't have to deal with path manipulation
-    env::set_current_dir("/etc/skel")?;
-
-    walk_dir(Path::new("."), &mut files, &mut dirs);
-
-    env::set_current_dir("/")?;
+    {
+        let mode = skel::skel_mode();
+        let vars = skel::skel_vars()?;
 
-    // recreate all the directories
-    for dir in &dirs {
-        let source = Path::new("/etc/skel").join(dir);
-        let dest = Path::new(&home).join(dir);
+        for layer in skel::skel_layers()? {
+            // image skel is always copied as it would be by useradd
+            let link = mode == SkelMode::Symlink && layer != Path::new("/etc/skel");
 
-        if !dest.exists() {
-            fs::create_dir(&dest)?;
+            skel::apply_layer(&layer, Path::new(&home), uid_u, gid_u, link, &vars)?;
         }
-
-        chown(&dest, Some(uid_u), Some(gid_u))?;
-
-        clone_perm(&source, &dest)?;
-    }
-
-    // clone all the files including symlinks
-    for file in &files {
-        let source = Path::new("/etc/skel").join(file);
-        let dest = Path::new(&home).join(file);
-
-        // NOTE fs::copy fails on broken symlinks
-        if source.is_symlink() {
-            symlink(source.read_link()?, &dest)?;
-        } else {
-            // NOTE it seems copy clones permissions as well so its fine
-            fs::copy(&source, &dest)?;
-        }
-
-        lchown(&dest, Some(uid_u), Some(gid_u))?;
     }
 
     // create the runtime dir whatever it is
@@ -204,32 +154,43 @@ fn initialization() -> Result<()> {
         fs::set_permissions(&dest, perm)?
     }
 
-    let has_sudo = Path::new("/bin/sudo").exists();
-    if has_sudo {
-        println!("Enabling passwordless sudo for everyone");
-
-        let mut file = OpenOptions::new().append(true).open("/etc/sudoers")?;
+    // create the xdg base dirs so apps do not create them as root or fail
+    for var in XDG_HOME_VARS {
+        let Ok(dest) = std::env::var(var) else {
+            continue;
+        };
 
-        // disable hostname resolving
-        writeln!(file, "Defaults !fqdn")?;
-
-        // allow everything without a password for everyone
-        writeln!(file, "ALL ALL = (ALL) NOPASSWD: ALL")?;
-    } else {
-        println!("Sudo not found, enabling passwordless su");
+        create_user_dir(Path::new(&dest), Path::new(&home), uid_u, gid_u)
+            .with_context(|| format!("Failed to create {} {:?}", var, dest))?;
+    }
 
-        // just remove root password for passwordless su
-        let code = Command::new("passwd")
-            .args(["-d", "root"])
-            .status()
-            .expect("Failed to execute passwd")
-            .get_code();
+    status::phase(InitPhase::Privilege, None)?;
+    privilege::setup_privilege(privilege::privilege_mode(), &user)?;
 
-        if code != 0 {
-            return Err(anyhow!("Error while setting passwd for root ({})", code));
+    // persistent volumes are owned by root when created
+    {
+        let paths = std::env::var(crate::ENV_PERSIST_USER).unwrap_or_default();
+        let paths: Vec<String> = if paths.is_empty() {
+            vec![]
+        } else {
+            serde_json::from_str(&paths).context("Failed to parse persist_user paths")?
+        };
+
+        for path in paths {
+            let path = match path.strip_prefix("~/") {
+                Some(x) => Path::new(&home).join(x),
+                None => PathBuf::from(path),
+            };
+
+            init_log::info(&format!("Changing ownership of {:?}", path));
+            chown(&path, Some(uid_u), Some(gid_u))
+                .with_context(|| format!("Failed to chown {:?}", path))?;
         }
     }
 
+    // root does not need a password to switch user
+    let has_sudo = crate::executable_in_path("sudo");
+
     let init_dir = Path::new(crate::INIT_D_DIR);
     if init_dir.exists() {
         let mut files: Vec<PathBuf> = vec![];
@@ -257,28 +218,40 @@ fn initialization() -> Result<()> {
         files.sort_by_key(|x| x.file_name().unwrap().to_owned());
 
         for file in files {
-            println!("Executing script {:?}", file);
-
-            // use sudo if available
-            if has_sudo {
-                Command::new("sudo")
-                    .args(["-u", &user])
-                    .arg(&file)
-                    .log_status_anyhow()
+            status::phase(InitPhase::Script, Some(&file.to_string_lossy()))?;
+            init_log::info(&format!("Executing script {:?}", file));
+
+            // some scripts need to run as root regardless of the privilege mode
+            let as_root = file
+                .file_name()
+                .is_some_and(|x| x.to_string_lossy().ends_with(crate::INIT_D_ROOT_SUFFIX));
+
+            let mut cmd = if as_root {
+                Command::new(&file)
+            } else if has_sudo {
+                // use sudo if available
+                let mut cmd = Command::new("sudo");
+                cmd.args(["-u", &user]).arg(&file);
+                cmd
             } else {
-                Command::new("su")
-                    .args([&user, "-c"])
-                    .arg(&file)
-                    .log_status_anyhow()
+                let mut cmd = Command::new("su");
+                cmd.args([&user, "-c"]).arg(&file);
+                cmd
+            };
+
+            let status = init_log::run_section(&file.to_string_lossy(), &mut cmd)
+                .with_context(|| anyhow!("Script {:?} has failed", file))?;
+
+            if !status.success() {
+                return Err(anyhow!("Script {:?} has failed with {}", file, status));
             }
-            .with_context(|| anyhow!("Script {:?} has failed", file))?;
         }
     }
 
-    // signalize that init is done
-    fs::write(crate::FLAG_FILE_INIT, "y")?;
+    init_log::info("Initialization finished");
 
-    println!("Initialization finished");
+    // signalize that init is done
+    status::phase(InitPhase::Finished, None)?;
 
     Ok(())
 }
@@ -291,30 +264,39 @@ pub fn container_init() -> Result<()> {
         }
     }
 
-    // small wrapper to run as root regardless if sudo is available
+    // small wrapper to run as root using whatever escalation is allowed
     std::fs::write(
         "/bin/asroot",
-        r#"#!/bin/sh
-set -e
-
-if command -v sudo >/dev/null; then
-    sudo -u root -g root -- "$@"
-else
-    su -c "$*" -g root root
-fi
-"#,
+        privilege::asroot_script(privilege::privilege_mode()),
     )?;
     make_executable(Path::new("/bin/asroot"))?;
 
-    // create the flag file to start preinit
+    // create the flag file to start preinit, the host removes it when its done
     std::fs::write(crate::FLAG_FILE_PRE_INIT, "y")?;
+    status::phase(InitPhase::Waiting, None)?;
 
     // wait for the flag file to be deleted to proceed
     while std::fs::exists(crate::FLAG_FILE_PRE_INIT)? {
         std::thread::sleep(std::time::Duration::from_millis(500));
     }
 
-    initialization()?;
+    init_log::open()?;
+
+    if let Err(err) = initialization() {
+        init_log::error(&format!("Initialization failed: {:?}", err));
+        status::failed(&format!("{:#}", err))?;
+
+        // keep the container alive until the host has read the status, it removes the file
+        for _ in 0..FAILED_STATUS_TIMEOUT_SECS {
+            if !fs::exists(crate::INIT_STATUS_FILE)? {
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+
+        return Err(err);
+    }
 
     // just sleep forever, podman-init will kill it
     loop {
@@ -0,0 +1,131 @@
+//! Init output written both to stdout and the init log file with timestamps
+
+use crate::command_extensions::*;
+use crate::prelude::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Formats time as UTC `YYYY-MM-DD HH:MM:SS`
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Creates the log file, any previous content is removed
+pub fn open() -> Result<()> {
+    let file = File::create(crate::INIT_LOG_FILE)
+        .with_context(|| format!("Failed to create init log {:?}", crate::INIT_LOG_FILE))?;
+
+    *LOG_FILE.lock().unwrap() = Some(file);
+
+    Ok(())
+}
+
+/// Appends line to the log file only
+fn write_line(line: &str) {
+    if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+        // logging should never stop the init
+        let _ = writeln!(file, "[{}] {}", format_timestamp(SystemTime::now()), line);
+    }
+}
+
+/// Prints the message and writes it into the log
+pub fn info(message: &str) {
+    println!("{}", message);
+    write_line(message);
+}
+
+/// Prints the error and writes it into the log
+pub fn error(message: &str) {
+    eprintln!("{}", message);
+    write_line(message);
+}
+
+/// Copies lines from the reader to the console and the log
+fn tee_lines(reader: impl Read, stderr: bool) {
+    let mut reader = BufReader::new(reader);
+    let mut buffer = Vec::new();
+
+    while reader.read_until(b'\n', &mut buffer).is_ok_and(|x| x > 0) {
+        let line = String::from_utf8_lossy(&buffer);
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        if stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+
+        write_line(line);
+        buffer.clear();
+    }
+}
+
+/// Runs the command in its own section of the log, capturing both stdout and stderr
+pub fn run_section(title: &str, cmd: &mut Command) -> Result<ExitStatus> {
+    write_line(&format!("==> {}", title));
+
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .log_spawn_anyhow()?;
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+
+    let stderr_thread = std::thread::spawn(move || tee_lines(stderr, true));
+    tee_lines(stdout, false);
+    let _ = stderr_thread.join();
+
+    let status = child.wait()?;
+    write_line(&format!("<== {} ({})", title, status));
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn init_log_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01 00:00:00");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(951_825_599)),
+            "2000-02-29 11:59:59"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(1_767_225_600)),
+            "2026-01-01 00:00:00"
+        );
+    }
+}
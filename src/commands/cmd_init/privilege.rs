@@ -0,0 +1,187 @@
+//! Setting up how the user gains root privileges inside the container
+
+use super::init_log;
+use crate::config::PrivilegeMode;
+use crate::prelude::*;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const SUDOERS_FILE: &str = "/etc/sudoers";
+const SUDOERS_DIR: &str = "/etc/sudoers.d";
+const DOAS_FILE: &str = "/etc/doas.conf";
+const DOAS_DIR: &str = "/etc/doas.d";
+
+/// Reads the privilege mode passed by start, defaults to sudo
+pub fn privilege_mode() -> PrivilegeMode {
+    use clap::ValueEnum;
+
+    std::env::var(crate::ENV_PRIVILEGE)
+        .ok()
+        .and_then(|x| PrivilegeMode::from_str(&x, true).ok())
+        .unwrap_or_default()
+}
+
+/// Contents of `asroot` wrapper for the privilege mode
+pub fn asroot_script(mode: PrivilegeMode) -> &'static str {
+    match mode {
+        PrivilegeMode::Sudo => {
+            r#"#!/bin/sh
+set -e
+
+if command -v sudo >/dev/null; then
+    sudo -u root -g root -- "$@"
+else
+    su -c "$*" -g root root
+fi
+"#
+        }
+        PrivilegeMode::Doas => {
+            r#"#!/bin/sh
+set -e
+
+exec doas -u root -- "$@"
+"#
+        }
+        PrivilegeMode::None => {
+            r#"#!/bin/sh
+
+echo "asroot: privilege escalation is disabled in this container" >&2
+exit 1
+"#
+        }
+    }
+}
+
+/// Contents of the sudoers drop-in file allowing only the user
+pub fn sudoers_rule(user: &str) -> String {
+    format!(
+        "# generated by {}\n\n# disable hostname resolving\nDefaults !fqdn\n\n{} ALL = (ALL) NOPASSWD: ALL\n",
+        crate::APP_NAME,
+        user
+    )
+}
+
+/// Checks if sudoers includes the drop-in directory
+pub fn sudoers_includes_dir(sudoers: &str) -> bool {
+    sudoers.lines().any(|x| {
+        let x = x.trim();
+        x == format!("#includedir {}", SUDOERS_DIR) || x == format!("@includedir {}", SUDOERS_DIR)
+    })
+}
+
+/// Writes file with specific mode, mode is set explicitly as the file may already exist
+fn write_with_mode(path: &Path, content: &str, mode: u32) -> Result<()> {
+    fs::write(path, content).with_context(|| format!("Failed to write {:?}", path))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {:?}", path))?;
+
+    Ok(())
+}
+
+/// Allows passwordless sudo for the user, returns false if sudo is not installed
+fn setup_sudo(user: &str) -> Result<bool> {
+    if !crate::executable_in_path("sudo") {
+        return Ok(false);
+    }
+
+    init_log::info(&format!("Enabling passwordless sudo for {:?}", user));
+
+    fs::create_dir_all(SUDOERS_DIR)?;
+
+    // sudo ignores files in sudoers.d that are writable so permissions matter
+    write_with_mode(
+        &Path::new(SUDOERS_DIR).join(crate::APP_NAME),
+        &sudoers_rule(user),
+        0o440,
+    )?;
+
+    // most distros include it by default but not all
+    let sudoers = fs::read_to_string(SUDOERS_FILE).unwrap_or_default();
+    if !sudoers_includes_dir(&sudoers) {
+        let mut sudoers = sudoers;
+        if !sudoers.is_empty() && !sudoers.ends_with('\n') {
+            sudoers.push('\n');
+        }
+        sudoers += &format!("#includedir {}\n", SUDOERS_DIR);
+
+        write_with_mode(Path::new(SUDOERS_FILE), &sudoers, 0o440)?;
+    }
+
+    Ok(true)
+}
+
+/// Allows passwordless doas for the user
+fn setup_doas(user: &str) -> Result<()> {
+    if !crate::executable_in_path("doas") {
+        return Err(anyhow!(
+            "Privilege mode is doas but doas is not installed in the image"
+        ));
+    }
+
+    init_log::info(&format!("Enabling passwordless doas for {:?}", user));
+
+    let rule = format!("permit nopass {} as root\n", user);
+
+    // some builds read drop-in files, otherwise append to the main config
+    if Path::new(DOAS_DIR).is_dir() {
+        write_with_mode(
+            &Path::new(DOAS_DIR).join(format!("{}.conf", crate::APP_NAME)),
+            &rule,
+            0o400,
+        )?;
+    } else {
+        let mut config = fs::read_to_string(DOAS_FILE).unwrap_or_default();
+        if !config.is_empty() && !config.ends_with('\n') {
+            config.push('\n');
+        }
+        config += &rule;
+
+        write_with_mode(Path::new(DOAS_FILE), &config, 0o400)?;
+    }
+
+    Ok(())
+}
+
+/// Sets up the escalation path for the user according to the mode
+pub fn setup_privilege(mode: PrivilegeMode, user: &str) -> Result<()> {
+    match mode {
+        PrivilegeMode::Sudo => {
+            if !setup_sudo(user)? {
+                init_log::info("Sudo not found, enabling passwordless su");
+
+                // just remove root password for passwordless su
+                super::user::remove_password("root")?;
+            }
+        }
+        PrivilegeMode::Doas => setup_doas(user)?,
+        PrivilegeMode::None => init_log::info("Privilege escalation is disabled"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn privilege_sudoers() {
+        let rule = sudoers_rule("user");
+        assert!(
+            rule.contains("\nuser ALL = (ALL) NOPASSWD: ALL\n"),
+            "{}",
+            rule
+        );
+        assert!(!rule.contains("ALL ALL"), "{}", rule);
+
+        assert!(sudoers_includes_dir(
+            "root ALL=(ALL) ALL\n#includedir /etc/sudoers.d\n"
+        ));
+        assert!(sudoers_includes_dir("@includedir /etc/sudoers.d"));
+        assert!(!sudoers_includes_dir("# @includedir /etc/sudoers.d"));
+
+        assert!(asroot_script(PrivilegeMode::None).contains("exit 1"));
+        assert!(asroot_script(PrivilegeMode::Doas).contains("doas"));
+    }
+}
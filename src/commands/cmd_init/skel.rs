@@ -0,0 +1,301 @@
+//! Applying skel layers into the user home, the image `/etc/skel` first then the mounted layers
+
+use super::init_log;
+use crate::config::SkelMode;
+use crate::prelude::*;
+use std::collections::BTreeMap;
+use std::os::unix::fs::{chown, lchown, symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Files with this suffix are rendered, the suffix is removed
+const TEMPLATE_SUFFIX: &str = ".tmpl";
+
+/// Walk recursively collecting files/symlinks into one vec, dirs into another, paths are relative
+/// to `base`
+fn walk_dir(base: &Path, dir: &Path, files: &mut Vec<PathBuf>, dirs: &mut Vec<PathBuf>) {
+    let iter = fs::read_dir(dir).unwrap();
+    for entry in iter {
+        match entry {
+            Ok(x) => match x.file_type() {
+                Ok(t) => {
+                    // strip prefix
+                    let clean_path = x.path().strip_prefix(base).unwrap().to_path_buf();
+
+                    if t.is_dir() {
+                        dirs.push(clean_path.to_path_buf());
+                        walk_dir(base, x.path().as_path(), files, dirs);
+                    } else if t.is_file() || t.is_symlink() {
+                        // save paths of files and symlinks
+                        files.push(clean_path.to_path_buf());
+                    } else {
+                        // all other files are errors cause they should not be there
+                        init_log::error(&format!("Invalid file type at {:?}", clean_path));
+                    }
+                }
+                Err(x) => init_log::error(&format!("Could not determine file type: {}", x)),
+            },
+            Err(x) => init_log::error(&format!("Error while reading directory: {}", x)),
+        }
+    }
+}
+
+/// Clone permissions between two paths, specificially `mode`
+fn clone_perm(source: &Path, dest: &Path) -> Result<(), std::io::Error> {
+    let source_perm = source.symlink_metadata()?.permissions();
+    let mut dest_perm = dest.symlink_metadata()?.permissions();
+
+    dest_perm.set_mode(source_perm.mode());
+
+    fs::set_permissions(dest, dest_perm)?;
+
+    Ok(())
+}
+
+/// Removes file or symlink from a previous layer so it can be replaced
+fn remove_existing(path: &Path) -> Result<()> {
+    if path.symlink_metadata().is_ok_and(|x| !x.is_dir()) {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the path template renders to, or `None` if the file is not a template
+pub fn template_destination(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_suffix(TEMPLATE_SUFFIX)?;
+
+    // a file named just `.tmpl` has nothing to render into
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(path.with_file_name(name))
+}
+
+/// Renders template using the variables passed by start, these include the host environment
+/// variables the templates reference
+///
+/// Templates were already checked on the host so missing variables are left as is, like in config
+/// with `ignore_missing_env`
+pub fn render_template(input: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let getter = |input: &str| -> Option<String> {
+        match input {
+            "RAND" | "RANDOM" => Some(rand::random::<u32>().to_string()),
+            _ => vars.get(input).cloned(),
+        }
+    };
+
+    Ok(crate::config::expand(input, &getter, false)?)
+}
+
+/// Reads how the skel layers should be applied, defaults to copy
+pub fn skel_mode() -> SkelMode {
+    use clap::ValueEnum;
+
+    env::var(crate::ENV_SKEL_MODE)
+        .ok()
+        .and_then(|x| SkelMode::from_str(&x, true).ok())
+        .unwrap_or_default()
+}
+
+/// Reads variables used to render the templates
+pub fn skel_vars() -> Result<BTreeMap<String, String>> {
+    match fs::read_to_string(crate::SKEL_VARS_FILE) {
+        Ok(x) => serde_json::from_str(&x).context("Failed to parse skel template variables"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err).context("Failed to read skel template variables"),
+    }
+}
+
+/// All layers in order they should be applied
+pub fn skel_layers() -> Result<Vec<PathBuf>> {
+    let mut layers = vec![PathBuf::from("/etc/skel")];
+
+    if Path::new(crate::SKEL_DIR).is_dir() {
+        let mut mounted = fs::read_dir(crate::SKEL_DIR)?
+            .flatten()
+            .map(|x| x.path())
+            .filter(|x| x.is_dir())
+            .collect::<Vec<_>>();
+
+        // layers are numbered so sorting keeps the order
+        mounted.sort();
+        layers.extend(mounted);
+    }
+
+    Ok(layers.into_iter().filter(|x| x.is_dir()).collect())
+}
+
+/// Applies single layer over the home, with `link` files are symlinked instead of copied
+pub fn apply_layer(
+    layer: &Path,
+    home: &Path,
+    uid: u32,
+    gid: u32,
+    link: bool,
+    vars: &BTreeMap<String, String>,
+) -> Result<()> {
+    init_log::info(&format!("Applying skel {:?}", layer));
+
+    let mut files: Vec<PathBuf> = vec![];
+    let mut dirs: Vec<PathBuf> = vec![];
+
+    walk_dir(layer, layer, &mut files, &mut dirs);
+
+    // recreate all the directories
+    for dir in &dirs {
+        let source = layer.join(dir);
+        let dest = home.join(dir);
+
+        if !dest.exists() {
+            fs::create_dir(&dest)?;
+        }
+
+        chown(&dest, Some(uid), Some(gid))?;
+
+        clone_perm(&source, &dest)?;
+    }
+
+    // clone all the files including symlinks, files from previous layers are replaced
+    for file in &files {
+        let source = layer.join(file);
+
+        if let Some(dest) = template_destination(&home.join(file)) {
+            let content = fs::read_to_string(&source)
+                .with_context(|| format!("Failed to read template {:?}", source))?;
+            let content = render_template(&content, vars)
+                .with_context(|| format!("Failed to render template {:?}", source))?;
+
+            remove_existing(&dest)?;
+            fs::write(&dest, content)?;
+            clone_perm(&source, &dest)?;
+            lchown(&dest, Some(uid), Some(gid))?;
+
+            continue;
+        }
+
+        let dest = home.join(file);
+        remove_existing(&dest)?;
+
+        if link {
+            symlink(&source, &dest)?;
+        } else if source.is_symlink() {
+            // NOTE fs::copy fails on broken symlinks
+            symlink(source.read_link()?, &dest)?;
+        } else {
+            // NOTE it seems copy clones permissions as well so its fine
+            fs::copy(&source, &dest)?;
+        }
+
+        lchown(&dest, Some(uid), Some(gid))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skel_templates() {
+        assert_eq!(
+            template_destination(Path::new("/home/user/.gitconfig.tmpl")),
+            Some(PathBuf::from("/home/user/.gitconfig"))
+        );
+        assert_eq!(
+            template_destination(Path::new("/home/user/.config/app.tmpl/file")),
+            None
+        );
+        assert_eq!(template_destination(Path::new("/home/user/.tmpl")), None);
+
+        let vars = BTreeMap::from([
+            ("USER".to_string(), "user".to_string()),
+            ("CONTAINER_NAME".to_string(), "box".to_string()),
+        ]);
+
+        assert_eq!(
+            render_template("name = $USER@${CONTAINER_NAME}\nprice = $$5\n", &vars).unwrap(),
+            "name = user@box\nprice = $5\n"
+        );
+        assert_eq!(
+            render_template("$ARCAM_TEST_UNDEFINED_VARIABLE", &vars).unwrap(),
+            "$ARCAM_TEST_UNDEFINED_VARIABLE"
+        );
+    }
+
+    #[test]
+    fn skel_apply_layers() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let home = tempfile::tempdir()?;
+        let first = tempfile::tempdir()?;
+        let second = tempfile::tempdir()?;
+        let meta = home.path().metadata()?;
+        let vars = BTreeMap::from([("USER".to_string(), "user".to_string())]);
+
+        fs::create_dir_all(first.path().join(".config").join("app"))?;
+        fs::write(first.path().join(".bashrc"), "first")?;
+        fs::write(first.path().join(".profile"), "first")?;
+        fs::write(first.path().join(".config").join("app").join("a"), "a")?;
+
+        fs::create_dir_all(second.path().join(".config").join("app"))?;
+        fs::write(second.path().join(".bashrc"), "second")?;
+        fs::write(second.path().join(".gitconfig.tmpl"), "name = $USER")?;
+        fs::write(second.path().join(".config").join("app").join("b"), "b")?;
+
+        apply_layer(
+            first.path(),
+            home.path(),
+            meta.uid(),
+            meta.gid(),
+            false,
+            &vars,
+        )?;
+        apply_layer(
+            second.path(),
+            home.path(),
+            meta.uid(),
+            meta.gid(),
+            false,
+            &vars,
+        )?;
+
+        // later layers replace files but keep the rest
+        assert_eq!(fs::read_to_string(home.path().join(".bashrc"))?, "second");
+        assert_eq!(fs::read_to_string(home.path().join(".profile"))?, "first");
+        assert!(home.path().join(".config/app/a").is_file());
+        assert!(home.path().join(".config/app/b").is_file());
+        assert!(!home.path().join(".bashrc").is_symlink());
+
+        assert_eq!(
+            fs::read_to_string(home.path().join(".gitconfig"))?,
+            "name = user"
+        );
+        assert!(!home.path().join(".gitconfig.tmpl").exists());
+
+        // copied files are replaced by symlinks into the layer, templates are still rendered
+        apply_layer(
+            second.path(),
+            home.path(),
+            meta.uid(),
+            meta.gid(),
+            true,
+            &vars,
+        )?;
+
+        assert_eq!(
+            home.path().join(".bashrc").read_link()?,
+            second.path().join(".bashrc")
+        );
+        assert!(!home.path().join(".gitconfig").is_symlink());
+        assert_eq!(
+            fs::read_to_string(home.path().join(".gitconfig"))?,
+            "name = user"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! Init status reported to the host through a JSON file, written by init and polled by start
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Phase the initialization is currently in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InitPhase {
+    /// Waiting for the host to copy everything into the container
+    Waiting,
+    User,
+    Skel,
+    FontCache,
+    Privilege,
+    Script,
+    Finished,
+    Failed,
+}
+
+impl std::fmt::Display for InitPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Waiting => "Waiting for host",
+            Self::User => "Setting up user",
+            Self::Skel => "Copying skel",
+            Self::FontCache => "Recreating font cache",
+            Self::Privilege => "Setting up privileges",
+            Self::Script => "Running script",
+            Self::Finished => "Finished",
+            Self::Failed => "Failed",
+        })
+    }
+}
+
+/// Step that was already completed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InitStep {
+    pub phase: InitPhase,
+
+    /// Name of the script for script phase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    pub duration_ms: u64,
+}
+
+impl std::fmt::Display for InitStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} {}", self.phase, name)?,
+            None => write!(f, "{}", self.phase)?,
+        }
+
+        write!(f, " ({:.1}s)", self.duration_ms as f64 / 1000.0)
+    }
+}
+
+/// Contents of the status file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InitStatus {
+    pub phase: InitPhase,
+
+    /// Name of the script currently running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,
+
+    #[serde(default)]
+    pub steps: Vec<InitStep>,
+
+    /// Error message when phase is failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl InitStatus {
+    /// Describes what is currently happening
+    pub fn current_step(&self) -> String {
+        match &self.current {
+            Some(name) => format!("{} {}", self.phase, name),
+            None => self.phase.to_string(),
+        }
+    }
+}
+
+/// Status being written by the init and when the current phase started
+struct Reporter {
+    status: InitStatus,
+    started: Instant,
+}
+
+static REPORTER: Mutex<Option<Reporter>> = Mutex::new(None);
+
+/// Writes the status atomically so the host never reads partial file
+fn write_status(status: &InitStatus) -> Result<()> {
+    let path = Path::new(crate::INIT_STATUS_FILE);
+    let tmp = path.with_extension("tmp");
+
+    fs::write(&tmp, serde_json::to_string(status)?)
+        .with_context(|| format!("Failed to write init status {:?}", tmp))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write init status {:?}", path))?;
+
+    Ok(())
+}
+
+/// Enters a new phase, previous phase is recorded as a completed step
+pub fn phase(phase: InitPhase, name: Option<&str>) -> Result<()> {
+    let mut reporter = REPORTER.lock().unwrap();
+
+    let reporter = match reporter.as_mut() {
+        Some(reporter) => {
+            let status = &mut reporter.status;
+
+            // waiting for the host is not part of the init
+            if status.phase != InitPhase::Waiting {
+                status.steps.push(InitStep {
+                    phase: status.phase,
+                    name: status.current.take(),
+                    duration_ms: reporter.started.elapsed().as_millis() as u64,
+                });
+            }
+
+            status.phase = phase;
+            status.current = name.map(str::to_string);
+            reporter.started = Instant::now();
+
+            reporter
+        }
+        None => reporter.insert(Reporter {
+            status: InitStatus {
+                phase,
+                current: name.map(str::to_string),
+                steps: vec![],
+                error: None,
+            },
+            started: Instant::now(),
+        }),
+    };
+
+    write_status(&reporter.status)
+}
+
+/// Marks the init as failed, the step it failed at is kept in `current`
+pub fn failed(error: &str) -> Result<()> {
+    let mut reporter = REPORTER.lock().unwrap();
+    let Some(reporter) = reporter.as_mut() else {
+        return Err(anyhow!("Init status was not initialized"));
+    };
+
+    let status = &mut reporter.status;
+    status.current = Some(status.current_step());
+    status.phase = InitPhase::Failed;
+    status.error = Some(error.to_string());
+
+    write_status(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_status_format() {
+        let status: InitStatus = serde_json::from_str(
+            r#"{"phase":"script","current":"/init.d/01_on_init_pre.sh","steps":[{"phase":"user","duration_ms":120},{"phase":"font_cache","duration_ms":1540}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(status.phase, InitPhase::Script);
+        assert_eq!(status.error, None);
+        assert_eq!(
+            status.current_step(),
+            "Running script /init.d/01_on_init_pre.sh"
+        );
+        assert_eq!(status.steps[0].to_string(), "Setting up user (0.1s)");
+        assert_eq!(status.steps[1].to_string(), "Recreating font cache (1.5s)");
+
+        let json = serde_json::to_string(&status).unwrap();
+        assert!(!json.contains("error"), "{}", json);
+        assert_eq!(serde_json::from_str::<InitStatus>(&json).unwrap(), status);
+    }
+}
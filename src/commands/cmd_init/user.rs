@@ -0,0 +1,377 @@
+//! User setup inside the container, works with shadow-utils, busybox or by editing the account
+//! databases directly when neither is available
+
+use super::init_log;
+use crate::command_extensions::*;
+use crate::prelude::*;
+use std::fs;
+use std::path::Path;
+
+const PASSWD_FILE: &str = "/etc/passwd";
+const GROUP_FILE: &str = "/etc/group";
+const SHADOW_FILE: &str = "/etc/shadow";
+
+/// User that should exist in the container
+#[derive(Debug, Clone)]
+pub struct User<'a> {
+    pub name: &'a str,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: &'a str,
+    pub shell: &'a str,
+}
+
+/// Finds entry in a colon separated database (passwd, group, shadow) where field at `index`
+/// equals `value`
+fn find_entry<'a>(db: &'a str, index: usize, value: &str) -> Option<Vec<&'a str>> {
+    db.lines()
+        .map(|x| x.split(':').collect::<Vec<_>>())
+        .find(|x| x.get(index) == Some(&value))
+}
+
+/// Appends line to the database making sure previous line is terminated
+fn append_line(db: &str, line: &str) -> String {
+    let mut output = db.to_string();
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+
+    output.push_str(line);
+    output.push('\n');
+
+    output
+}
+
+/// Replaces fields in entry named `name`, returns `None` if the entry does not exist
+fn modify_entry(db: &str, name: &str, fields: &[(usize, &str)]) -> Option<String> {
+    find_entry(db, 0, name)?;
+
+    let mut output = db
+        .lines()
+        .map(|line| {
+            let mut entry = line.split(':').collect::<Vec<_>>();
+            if entry.first() != Some(&name) {
+                return line.to_string();
+            }
+
+            for (index, value) in fields {
+                if entry.len() <= *index {
+                    entry.resize(index + 1, "");
+                }
+
+                entry[*index] = value;
+            }
+
+            entry.join(":")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if db.ends_with('\n') {
+        output.push('\n');
+    }
+
+    Some(output)
+}
+
+/// Adds user to passwd or updates its home and shell if it already exists
+pub fn passwd_set_user(passwd: &str, user: &User, password: &str) -> String {
+    modify_entry(passwd, user.name, &[(5, user.home), (6, user.shell)]).unwrap_or_else(|| {
+        append_line(
+            passwd,
+            &format!(
+                "{}:{}:{}:{}::{}:{}",
+                user.name, password, user.uid, user.gid, user.home, user.shell
+            ),
+        )
+    })
+}
+
+/// Adds group with `gid` unless one already exists, returns `None` if nothing changed
+pub fn group_ensure(group: &str, name: &str, gid: u32) -> Option<String> {
+    if find_entry(group, 2, &gid.to_string()).is_some() {
+        return None;
+    }
+
+    if find_entry(group, 0, name).is_some() {
+        log::warn!("Group {name:?} already exists with different gid, not creating group {gid}");
+        return None;
+    }
+
+    Some(append_line(group, &format!("{}:x:{}:", name, gid)))
+}
+
+/// Adds locked shadow entry for the user if missing, returns `None` if nothing changed
+pub fn shadow_ensure_user(shadow: &str, name: &str) -> Option<String> {
+    if find_entry(shadow, 0, name).is_some() {
+        return None;
+    }
+
+    Some(append_line(shadow, &format!("{}:!::0:99999:7:::", name)))
+}
+
+/// Name of the group with `gid` for busybox `adduser`, and whether it has to be created first
+///
+/// Existing group with the user name but different gid is kept as is, the group is created with
+/// the gid appended to the name instead as `addgroup` would fail
+pub fn busybox_group(group: &str, name: &str, gid: u32) -> (String, bool) {
+    if let Some(entry) = find_entry(group, 2, &gid.to_string()) {
+        return (entry[0].to_string(), false);
+    }
+
+    if find_entry(group, 0, name).is_some() {
+        let renamed = format!("{}{}", name, gid);
+        log::warn!("Group {name:?} already exists with different gid, creating group {renamed:?}");
+
+        return (renamed, true);
+    }
+
+    (name.to_string(), true)
+}
+
+/// Reads database, missing file is treated as empty
+fn read_db(path: &str) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(x) => Ok(x),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {:?}", path)),
+    }
+}
+
+fn write_db(path: &str, content: &str) -> Result<()> {
+    fs::write(path, content).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// Check if user exists without relying on `getent`
+pub fn user_exists(name: &str) -> Result<bool> {
+    Ok(find_entry(&read_db(PASSWD_FILE)?, 0, name).is_some())
+}
+
+/// Check if the command is a busybox applet
+fn is_busybox(cmd: &str) -> bool {
+    Command::new(cmd)
+        .arg("--help")
+        .log_output()
+        .map(|x| {
+            String::from_utf8_lossy(&x.stdout).contains("BusyBox")
+                || String::from_utf8_lossy(&x.stderr).contains("BusyBox")
+        })
+        .unwrap_or(false)
+}
+
+/// Creates or modifies user using shadow-utils
+fn setup_user_shadow_utils(user: &User, exists: bool) -> Result<()> {
+    let status = if !exists {
+        Command::new("useradd")
+            .args([
+                "--shell",
+                user.shell,
+                "--home-dir",
+                user.home,
+                "--uid",
+                &user.uid.to_string(),
+                "--user-group",
+                "--no-create-home",
+                user.name,
+            ])
+            .log_status_anyhow()?
+    } else {
+        Command::new("usermod")
+            .args(["--home", user.home, "--shell", user.shell, user.name])
+            .log_status_anyhow()?
+    };
+
+    if !status.success() {
+        return Err(anyhow!("Error while setting up the user"));
+    }
+
+    Ok(())
+}
+
+/// Creates user using busybox applets, busybox has no usermod so it can only create users
+fn setup_user_busybox(user: &User) -> Result<()> {
+    let gid = user.gid.to_string();
+
+    let (group_name, create) = busybox_group(&read_db(GROUP_FILE)?, user.name, user.gid);
+    if create {
+        let status = Command::new("addgroup")
+            .args(["-g", &gid, &group_name])
+            .log_status_anyhow()?;
+
+        if !status.success() {
+            return Err(anyhow!("Error while creating the user group"));
+        }
+    }
+
+    // adduser requires group name instead of gid
+    let group = read_db(GROUP_FILE)?;
+    let group_name = find_entry(&group, 2, &gid)
+        .map(|x| x[0])
+        .ok_or_else(|| anyhow!("Group with gid {} does not exist", gid))?;
+
+    let status = Command::new("adduser")
+        .args([
+            "-D",
+            "-H",
+            "-h",
+            user.home,
+            "-s",
+            user.shell,
+            "-u",
+            &user.uid.to_string(),
+            "-G",
+            group_name,
+            user.name,
+        ])
+        .log_status_anyhow()?;
+
+    if !status.success() {
+        return Err(anyhow!("Error while setting up the user"));
+    }
+
+    Ok(())
+}
+
+/// Creates or modifies user by editing the databases directly
+fn setup_user_files(user: &User) -> Result<()> {
+    let has_shadow = Path::new(SHADOW_FILE).exists();
+
+    if let Some(group) = group_ensure(&read_db(GROUP_FILE)?, user.name, user.gid) {
+        write_db(GROUP_FILE, &group)?;
+    }
+
+    let passwd = passwd_set_user(
+        &read_db(PASSWD_FILE)?,
+        user,
+        if has_shadow { "x" } else { "!" },
+    );
+    write_db(PASSWD_FILE, &passwd)?;
+
+    if has_shadow {
+        if let Some(shadow) = shadow_ensure_user(&read_db(SHADOW_FILE)?, user.name) {
+            write_db(SHADOW_FILE, &shadow)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the user or updates its home and shell if it already exists
+pub fn setup_user(user: &User) -> Result<()> {
+    let exists = user_exists(user.name)?;
+
+    if exists {
+        init_log::info(&format!("Modifying user {:?}", user.name));
+    } else {
+        init_log::info(&format!("Creating user {:?}", user.name));
+    }
+
+    if crate::executable_in_path("useradd") && crate::executable_in_path("usermod") {
+        setup_user_shadow_utils(user, exists)
+    } else if !exists && crate::executable_in_path("adduser") && is_busybox("adduser") {
+        log::debug!("Using busybox adduser");
+        setup_user_busybox(user)
+    } else {
+        log::debug!("User management tools not found, editing {PASSWD_FILE:?} directly");
+        setup_user_files(user)
+    }
+}
+
+/// Removes password of the user so `su` does not ask for it
+pub fn remove_password(name: &str) -> Result<()> {
+    if crate::executable_in_path("passwd") {
+        let code = Command::new("passwd")
+            .args(["-d", name])
+            .log_status_anyhow()?
+            .get_code();
+
+        if code != 0 {
+            return Err(anyhow!(
+                "Error while removing password for {} ({})",
+                name,
+                code
+            ));
+        }
+
+        return Ok(());
+    }
+
+    // password is in shadow if it exists otherwise in passwd
+    let path = if Path::new(SHADOW_FILE).exists() {
+        SHADOW_FILE
+    } else {
+        PASSWD_FILE
+    };
+
+    let db = modify_entry(&read_db(path)?, name, &[(1, "")])
+        .ok_or_else(|| anyhow!("User {:?} does not exist in {:?}", name, path))?;
+
+    write_db(path, &db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_databases() {
+        let user = User {
+            name: "user",
+            uid: 1000,
+            gid: 1000,
+            home: "/home/user",
+            shell: "/bin/sh",
+        };
+
+        let passwd = "root:x:0:0:root:/root:/bin/ash\nbin:x:1:1:bin:/bin:/sbin/nologin";
+
+        let added = passwd_set_user(passwd, &user, "x");
+        assert_eq!(
+            added,
+            "root:x:0:0:root:/root:/bin/ash\nbin:x:1:1:bin:/bin:/sbin/nologin\nuser:x:1000:1000::/home/user:/bin/sh\n"
+        );
+
+        let modified =
+            passwd_set_user("user:x:1000:1000:Me:/var/empty:/sbin/nologin\n", &user, "x");
+        assert_eq!(modified, "user:x:1000:1000:Me:/home/user:/bin/sh\n");
+
+        assert_eq!(
+            group_ensure("root:x:0:\nusers:x:1000:\n", "user", 1000),
+            None
+        );
+        assert_eq!(
+            group_ensure("root:x:0:\n", "user", 1000).as_deref(),
+            Some("root:x:0:\nuser:x:1000:\n")
+        );
+
+        assert_eq!(shadow_ensure_user("user:!:::::::\n", "user"), None);
+        assert_eq!(
+            shadow_ensure_user("root:*:::::::\n", "user").as_deref(),
+            Some("root:*:::::::\nuser:!::0:99999:7:::\n")
+        );
+
+        assert_eq!(
+            modify_entry("root:*:19000:0:::::\n", "root", &[(1, "")]).as_deref(),
+            Some("root::19000:0:::::\n")
+        );
+        assert_eq!(modify_entry("root:*:::::::\n", "nobody", &[(1, "")]), None);
+    }
+
+    #[test]
+    fn user_busybox_group() {
+        assert_eq!(
+            busybox_group("root:x:0:\nusers:x:1000:\n", "user", 1000),
+            ("users".to_string(), false)
+        );
+        assert_eq!(
+            busybox_group("root:x:0:\n", "user", 1000),
+            ("user".to_string(), true)
+        );
+
+        // group with the same name but different gid is left alone
+        assert_eq!(
+            busybox_group("root:x:0:\nuser:x:100:\n", "user", 1000),
+            ("user1000".to_string(), true)
+        );
+    }
+}
@@ -47,6 +47,16 @@ pub fn kill_container(ctx: Context, mut cli_args: cli::CmdKillArgs) -> Result<()
         return Err(anyhow!("Cancelled by user."));
     }
 
+    // overlay has to be removed manually after the container is gone
+    let overlay_dir = if ctx.dry_run {
+        None
+    } else {
+        ctx.engine
+            .inspect_containers(vec![&cli_args.name])?
+            .first()
+            .and_then(|x| x.labels.get(crate::CONTAINER_LABEL_OVERLAY_DIR).cloned())
+    };
+
     let timeout = cli_args.timeout.to_string();
     let mut cmd = ctx.engine.command();
     cmd.args(["container", "stop", "--time", &timeout, &cli_args.name]);
@@ -57,6 +67,11 @@ pub fn kill_container(ctx: Context, mut cli_args: cli::CmdKillArgs) -> Result<()
         cmd.log_output_anyhow()?;
     }
 
+    if let Some(overlay_dir) = overlay_dir {
+        log::debug!("Removing workspace overlay {overlay_dir:?}");
+        crate::commands::remove_overlay_dir(&ctx, std::path::Path::new(&overlay_dir))?;
+    }
+
     Ok(())
 }
 
@@ -15,10 +15,51 @@ pub fn print_logs(ctx: Context, mut cli_args: cli::CmdLogsArgs) -> Result<()> {
         cli_args.name = containers.first().unwrap().clone();
     }
 
-    println!("The logs may be empty if the container name is not valid");
+    if cli_args.init {
+        // read the log from the container itself
+        let mut cmd = ctx.engine.command();
+        cmd.args(["exec", "--user", "root", &cli_args.name]);
 
-    let mut cmd = Command::new("journalctl");
-    cmd.args(["-t", &cli_args.name]);
+        if cli_args.follow {
+            cmd.args(["tail", "-n", "+1", "-f", crate::INIT_LOG_FILE]);
+        } else {
+            cmd.args(["cat", crate::INIT_LOG_FILE]);
+        }
+
+        let status = cmd.log_status()?;
+        if !status.success() {
+            return Err(anyhow!(
+                "Failed to read init log from container {:?}",
+                cli_args.name
+            ));
+        }
+
+        return Ok(());
+    }
+
+    // removed containers cannot be inspected but their logs may still be in the journal
+    let log_driver = ctx
+        .engine
+        .inspect_containers(vec![&cli_args.name])
+        .ok()
+        .and_then(|x| x.into_iter().next())
+        .map(|x| x.log_driver);
+
+    let mut cmd = match log_driver.as_deref() {
+        None | Some("journald") => {
+            println!("The logs may be empty if the container name is not valid");
+
+            let mut cmd = Command::new("journalctl");
+            cmd.args(["-t", &cli_args.name]);
+            cmd
+        }
+        // other drivers can be read by the engine directly
+        Some(_) => {
+            let mut cmd = ctx.engine.command();
+            cmd.args(["logs", &cli_args.name]);
+            cmd
+        }
+    };
 
     if cli_args.follow {
         // follow the output
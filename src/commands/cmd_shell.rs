@@ -31,6 +31,11 @@ pub fn open_shell(ctx: Context, mut cli_args: cli::CmdShellArgs) -> Result<()> {
         ));
     };
 
+    // start in the same directory if cwd is mounted in the container
+    let workdir = crate::container_workdir(&container_info.labels, &ctx.cwd)
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| ws_dir.to_string());
+
     let mut cmd = ctx.engine.command();
 
     let Some(user_shell) = container_info.labels.get(crate::CONTAINER_LABEL_USER_SHELL) else {
@@ -52,7 +57,7 @@ pub fn open_shell(ctx: Context, mut cli_args: cli::CmdShellArgs) -> Result<()> {
         format!("--env=HOME=/home/{}", ctx.user),
         format!("--env=SHELL={}", user_shell),
         "--workdir".into(),
-        ws_dir.to_string(),
+        workdir,
         "--user".into(),
         ctx.user.clone(),
         cli_args.name.clone(),
@@ -2,13 +2,18 @@ mod util;
 
 use crate::cli::{CmdStartArgs, ConfigArg};
 use crate::command_extensions::*;
+use crate::commands::cmd_init::status::InitPhase;
+use crate::config::{SELinuxMode, SecretSource, WorkspaceMode};
+use crate::policy::Policy;
 use crate::prelude::*;
 use crate::{APP_NAME, ENV_VAR_PREFIX, VERSION};
-use std::path::PathBuf;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use util::*;
 
 pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
-    let executable_path = ctx.get_executable_path()?;
+    let mut executable_path = ctx.get_executable_path()?;
 
     // NOTE /ws/ prefix is used so it does not clash with home dirs like ~/.config
     //
@@ -23,6 +28,9 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
         ctx.cwd.file_name().unwrap().to_string_lossy()
     );
 
+    // policy is enforced regardless of the config used
+    let policy = Policy::from_file(&ctx.policy_file())?;
+
     // get containers in this cwd, i do not care if it fails
     let cwd_containers = ctx.get_cwd_containers()?;
     if !cwd_containers.is_empty() {
@@ -39,14 +47,36 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
     let on_init_post: String;
     let mut persist: Vec<(String, String)> = vec![];
     let mut persist_user: Vec<(String, String)> = vec![];
+    let mut packages_override: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let cache_volume: String;
+
+    // whether undefined variables are an error in config and skel templates
+    let strict_env: bool;
+
+    // home created by init, not the host home
+    let container_home = format!("/home/{}", ctx.user);
 
     log::debug!("Container name set to {container_name:?}");
 
-    // TODO shellexpand env expansion should error out!
+    // variables used in config expansion, also used to render skel templates in the container
+    let mut template_vars: BTreeMap<String, String> = BTreeMap::new();
+    {
+        let pwd = ctx.cwd.to_string_lossy().to_string();
+
+        template_vars.insert("USER".into(), ctx.user.clone());
+        template_vars.insert("PWD".into(), pwd.clone());
+        template_vars.insert("CWD".into(), pwd);
+        template_vars.insert("HOME".into(), ctx.user_home.to_string_lossy().to_string());
+        template_vars.insert("CONTAINER".into(), container_name.clone());
+        template_vars.insert("CONTAINER_NAME".into(), container_name.clone());
+    }
+
     if let ConfigArg::Image(image) = &cli_args.config {
         // no config used
 
         container_image = image.to_string();
+        cache_volume = cache_volume_name(image);
+        strict_env = true;
         on_init_pre = "".into();
         on_init_post = "".into();
     } else {
@@ -70,8 +100,11 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
         };
 
         let config_name = config.name.expect("Config name is not set after loading!");
+        cache_volume = cache_volume_name(&config_name);
 
         if let Some(host_pre_init) = &config.host_pre_init {
+            policy.check_host_pre_init()?;
+
             // avoid infinite loop using env var
             if std::env::var(crate::ENV_EXE_PATH).is_err() {
                 use std::os::unix::process::CommandExt;
@@ -99,82 +132,243 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
             }
         }
 
-        // use config image
-        container_image = config.image.clone();
-
         // expand vars
-        let pwd = ctx.cwd.to_string_lossy();
-        let home = ctx.user_home.to_string_lossy();
+        template_vars.insert(
+            "CONFIG_DIR".into(),
+            config_dir.to_string_lossy().to_string(),
+        );
+        template_vars.insert("CONFIG_NAME".into(), config_name.clone());
 
         let context_getter = |input: &str| -> Option<String> {
             match input {
-                "USER" => Some(ctx.user.clone()),
-                "PWD" | "CWD" => Some(pwd.to_string()),
-                "HOME" => Some(home.to_string()),
-                "CONTAINER" | "CONTAINER_NAME" => Some(container_name.clone()),
                 "RAND" | "RANDOM" => Some(rand::random::<u32>().to_string()),
-                "CONFIG_DIR" => Some(config_dir.to_string_lossy().to_string()),
-                "CONFIG_NAME" => Some(config_name.clone()),
 
                 // fallback to environ
-                _ => {
-                    if let Ok(var) = std::env::var(input) {
-                        Some(var)
-                    } else {
-                        log::warn!("Could not expand {input:?} in config");
-                        None
-                    }
-                }
+                _ => template_vars
+                    .get(input)
+                    .cloned()
+                    .or_else(|| std::env::var(input).ok()),
             }
         };
 
+        strict_env = !config.ignore_missing_env;
+        let config_path = config.path.as_ref().unwrap();
+        let expand = |field: &str, input: &str| -> Result<String> {
+            crate::config::expand(input, &context_getter, strict_env).with_context(|| {
+                format!(
+                    "while expanding field `{}` in config {:?}",
+                    field, config_path
+                )
+            })
+        };
+
+        // use config image
+        container_image = expand("image", &config.image)?;
+
         // expand vars in engine args and append to cli args
         for i in config.engine_args.iter() {
-            cli_args
-                .engine_args
-                .push(shellexpand::env_with_context_no_errors(&i, context_getter).to_string());
+            cli_args.engine_args.push(expand("engine_args", i)?);
         }
 
-        // cli skel takes priority
-        if cli_args.skel.is_none() {
-            if let Some(skel) = config.skel {
-                cli_args.skel = Some(
-                    shellexpand::env_with_context_no_errors(&skel, context_getter).to_string(),
-                );
-            }
+        // config mounts come first, sources are relative to the config dir
+        let mut mounts = vec![];
+        for mount in &config.mounts {
+            mount
+                .validate()
+                .map_err(|x| anyhow!(x))
+                .with_context(|| format!("in config {:?}", config_path))?;
+
+            let source = expand("mounts", &mount.source)?;
+            let source = match source.strip_prefix("~/") {
+                Some(x) => ctx.user_home.join(x),
+                None => config_dir.join(source),
+            };
+
+            mounts.push(crate::config::Mount {
+                source: source.to_string_lossy().to_string(),
+                destination: mount
+                    .destination
+                    .as_ref()
+                    .map(|x| expand("mounts", x))
+                    .transpose()?,
+                ..mount.clone()
+            });
+        }
+        mounts.append(&mut cli_args.mount);
+        cli_args.mount = mounts;
+
+        // config skel layers are applied before the cli ones, relative to the config dir
+        let mut skel = vec![];
+        for dir in &config.skel {
+            let dir = expand("skel", dir)?;
+            let dir = match dir.strip_prefix("~/") {
+                Some(x) => ctx.user_home.join(x),
+                None => config_dir.join(dir),
+            };
+
+            skel.push(dir.to_string_lossy().to_string());
         }
+        skel.append(&mut cli_args.skel);
+        cli_args.skel = skel;
 
         // expand env as well for some fun dynamic shennanigans
         for (k, v) in &config.env {
-            let mapped = format!("{k}={v}");
-            cli_args
-                .env
-                .push(shellexpand::env_with_context_no_errors(&mapped, context_getter).to_string());
+            cli_args.env.push(format!("{}={}", k, expand("env", v)?));
+        }
+
+        // config files are relative to the config dir, cli ones to cwd
+        for file in &config.env_file {
+            let file = expand("env_file", file)?;
+            let file = match file.strip_prefix("~/") {
+                Some(x) => ctx.user_home.join(x),
+                None => config_dir.join(file),
+            };
+
+            cli_args.env_file.push(file.to_string_lossy().to_string());
+        }
+
+        cli_args
+            .env_passthrough
+            .extend_from_slice(&config.env_passthrough);
+
+        for package in config
+            .packages
+            .iter()
+            .chain(config.packages_override.values().flatten())
+        {
+            crate::config::validate_package_name(package).map_err(|x| anyhow!(x))?;
+        }
+
+        if let Some(manager) = config
+            .packages_override
+            .keys()
+            .find(|x| !crate::config::PACKAGE_MANAGERS.contains(&x.as_str()))
+        {
+            return Err(anyhow!(
+                "Unknown package manager {:?} in packages_override, expected one of {}",
+                manager,
+                crate::config::PACKAGE_MANAGERS.join(", ")
+            ));
         }
 
+        cli_args.packages.extend_from_slice(&config.packages);
+        packages_override = config.packages_override.clone();
+
+        // secrets from cli take priority
+        for (name, source) in &config.secrets {
+            crate::config::validate_secret_name(name).map_err(|x| anyhow!(x))?;
+
+            if cli_args.secrets.iter().any(|(x, _)| x == name) {
+                continue;
+            }
+
+            let source = match source {
+                SecretSource::File(file) => {
+                    let file = expand("secrets", file)?;
+                    let file = match file.strip_prefix("~/") {
+                        Some(x) => ctx.user_home.join(x),
+                        None => config_dir.join(file),
+                    };
+
+                    SecretSource::File(file.to_string_lossy().to_string())
+                }
+                x => x.clone(),
+            };
+
+            cli_args.secrets.push((name.clone(), source));
+        }
+
+        for port in &config.ports {
+            cli_args.ports.push(port.resolve(|x| expand("ports", x))?);
+        }
+
+        // get the persist paths
+        for (vol, path) in &config.persist {
+            persist.push((expand("persist", vol)?, expand("persist", path)?));
+        }
+
+        for (vol, path) in &config.persist_user {
+            persist_user.push((expand("persist_user", vol)?, expand("persist_user", path)?));
+        }
+
+        // cli profile takes priority, config one is relative to the config dir
+        if cli_args.seccomp_profile.is_none() {
+            if let Some(profile) = &config.seccomp_profile {
+                let profile = expand("seccomp_profile", profile)?;
+                cli_args.seccomp_profile =
+                    Some(config_dir.join(profile).to_string_lossy().to_string());
+            }
+        }
+
+        // concatinate pre / post init
+        on_init_pre = cli_args
+            .on_init_pre
+            .iter()
+            .cloned()
+            .chain(
+                config
+                    .on_init_pre
+                    .as_ref()
+                    .map(|x| expand("on_init_pre", x))
+                    .transpose()?,
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+        on_init_post = cli_args
+            .on_init_post
+            .iter()
+            .cloned()
+            .chain(
+                config
+                    .on_init_post
+                    .as_ref()
+                    .map(|x| expand("on_init_post", x))
+                    .transpose()?,
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+
         // prefer options from cli
         cli_args.shell = cli_args.shell.or(config.shell);
         cli_args.network = cli_args.network.or(Some(config.network));
         cli_args.pipewire = cli_args.pipewire.or(Some(config.pipewire));
         cli_args.pulseaudio = cli_args.pulseaudio.or(Some(config.pulseaudio));
         cli_args.wayland = cli_args.wayland.or(Some(config.wayland));
+        cli_args.x11 = cli_args.x11.or(Some(config.x11));
         cli_args.ssh_agent = cli_args.ssh_agent.or(Some(config.ssh_agent));
         cli_args.session_bus = cli_args.session_bus.or(Some(config.session_bus));
-        cli_args.ports.extend_from_slice(&config.ports);
+        cli_args.selinux = cli_args.selinux.or(Some(config.selinux));
+        cli_args.privilege = cli_args.privilege.or(Some(config.privilege));
+        cli_args.skel_mode = cli_args.skel_mode.or(Some(config.skel_mode));
+        cli_args.cache_volume = cli_args.cache_volume.or(Some(config.cache_volume));
+        cli_args.workspace = cli_args.workspace.or(Some(config.workspace));
         cli_args
             .capabilities
             .extend_from_slice(&config.capabilities);
+        cli_args.no_new_privileges = cli_args
+            .no_new_privileges
+            .or(Some(config.no_new_privileges));
+
+        // config goes first so cli can override it
+        let mut deny_syscalls = config.deny_syscalls;
+        deny_syscalls.append(&mut cli_args.deny_syscalls);
+        cli_args.deny_syscalls = deny_syscalls;
+    }
 
-        // get the persist paths
-        persist = config.persist;
-        persist_user = config.persist_user;
+    log::debug!("Using image {container_image:?}");
 
-        // concatinate pre / post init
-        on_init_pre = cli_args.on_init_pre.join("\n") + &config.on_init_pre.unwrap_or_default();
-        on_init_post = cli_args.on_init_post.join("\n") + &config.on_init_post.unwrap_or_default();
+    // relative skel paths are relative to cwd
+    for skel in cli_args.skel.iter_mut() {
+        let path = ctx.cwd.join(skel.strip_prefix("./").unwrap_or(skel));
+        *skel = path.to_string_lossy().to_string();
     }
 
-    log::debug!("Using image {container_image:?}");
+    // checked before any of the skel layers are read
+    policy.check_start(&cli_args, &container_image)?;
+
+    // templates are rendered in the container but use the host environment like config does
+    let skel_vars = skel_template_vars(&cli_args.skel, &template_vars, strict_env)?;
+    template_vars.extend(skel_vars);
 
     // allow dry-run regardless if the container exists
     if !ctx.dry_run && ctx.engine.container_exists(&container_name)? {
@@ -191,12 +385,28 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
 
     log::info!("Using {:?} as the shell", cli_args.shell);
 
+    let selinux = cli_args.selinux.unwrap_or_default();
+    if selinux.is_enabled() {
+        log::debug!("Using SELinux labeling {selinux:?}");
+
+        // relabeling the executable in place could break it on the host
+        executable_path = copy_executable(&ctx, &executable_path)?;
+
+        // everything except network is a socket
+        if cli_args
+            .permissions()
+            .iter()
+            .any(|(name, enabled)| *enabled && *name != "network")
+        {
+            log::warn!("Passing through sockets may not work with SELinux labeling enabled");
+        }
+    }
+
     let mut cmd = ctx.engine.command();
     cmd.args([
         "run",
         "-d",
         "--rm",
-        "--security-opt=label=disable",
         "--user=root",
         // arcam does not act as the init system anymore
         "--init",
@@ -204,6 +414,10 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
         "--detach-keys=",
     ]);
 
+    if !selinux.is_enabled() {
+        cmd.arg("--security-opt=label=disable");
+    }
+
     cmd.args([
         format!("--name={}", container_name),
         format!("--label=manager={}", ctx.engine),
@@ -231,22 +445,91 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
         format!("--env=HOST_USER={}", ctx.user),
         format!("--env=HOST_USER_UID={}", ctx.user_id),
         format!("--env=HOST_USER_GID={}", ctx.user_gid),
-        // TODO explore all the xdg dirs and set them properly
         format!("--env=XDG_RUNTIME_DIR=/run/user/{}", ctx.user_id),
+        format!("--env=XDG_CONFIG_HOME={}/.config", container_home),
+        format!("--env=XDG_DATA_HOME={}/.local/share", container_home),
+        format!("--env=XDG_STATE_HOME={}/.local/state", container_home),
+        format!("--env=XDG_CACHE_HOME={}/.cache", container_home),
         format!(
-            "--volume={}:{}",
-            ctx.cwd.to_string_lossy(),
-            main_project_dir
+            "--env={}={}",
+            crate::ENV_PRIVILEGE,
+            cli_args
+                .privilege
+                .unwrap_or_default()
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+        ),
+        format!(
+            "--env={}={}",
+            crate::ENV_PERSIST_USER,
+            serde_json::to_string(&persist_user.iter().map(|(_, x)| x).collect::<Vec<_>>())?
+        ),
+        format!(
+            "--env={}={}",
+            crate::ENV_SKEL_MODE,
+            cli_args
+                .skel_mode
+                .unwrap_or_default()
+                .to_possible_value()
+                .unwrap()
+                .get_name()
         ),
         format!(
-            "--volume={}:{}:ro,nocopy",
+            "--volume={}:{}{}",
             executable_path.display(),
-            crate::ARCAM_EXE
+            crate::ARCAM_EXE,
+            // the executable copy is shared between all containers
+            volume_options(
+                if selinux.is_enabled() {
+                    SELinuxMode::Shared
+                } else {
+                    selinux
+                },
+                &["ro", "nocopy"]
+            )
         ),
         format!("--entrypoint={}", crate::ARCAM_EXE),
-        format!("--hostname={}", get_hostname()?),
+        format!("--hostname={}", crate::get_hostname()?),
     ]);
 
+    // overlays of containers that exited without kill would be left behind forever
+    remove_stale_overlay_dirs(&ctx)?;
+
+    match cli_args.workspace.unwrap_or_default() {
+        WorkspaceMode::Rw => cmd.arg(format!(
+            "--volume={}:{}{}",
+            ctx.cwd.to_string_lossy(),
+            main_project_dir,
+            volume_options(selinux, &[])
+        )),
+        WorkspaceMode::Ro => cmd.arg(format!(
+            "--volume={}:{}{}",
+            ctx.cwd.to_string_lossy(),
+            main_project_dir,
+            volume_options(selinux, &["ro"])
+        )),
+        WorkspaceMode::Overlay => {
+            let overlay_dir = prepare_overlay_dir(&ctx, &container_name)?;
+
+            // NOTE: overlay is always labeled private by the engine so no relabeling
+            cmd.args([
+                format!(
+                    "--label={}={}",
+                    crate::CONTAINER_LABEL_OVERLAY_DIR,
+                    overlay_dir.to_string_lossy()
+                ),
+                format!(
+                    "--volume={}:{}:O,upperdir={},workdir={}",
+                    ctx.cwd.to_string_lossy(),
+                    main_project_dir,
+                    overlay_dir.join("upper").to_string_lossy(),
+                    overlay_dir.join("work").to_string_lossy(),
+                ),
+            ])
+        }
+    };
+
     cmd.args([
         "--userns=keep-id",
         "--group-add=keep-groups",
@@ -256,6 +539,12 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
         "--tz=local",
     ]);
 
+    // engine gives priority to --env over --env-file, and the last --env wins, the generated file
+    // is removed on drop
+    let generated_env_file = resolve_env_files(&ctx, &cli_args, &container_name, &mut cmd)?;
+    cmd.args(env_passthrough_args(&cli_args.env_passthrough));
+    cmd.args(secret_args(&ctx, &cli_args, &container_name));
+
     // add the env vars
     for e in &cli_args.env {
         cmd.arg(format!("--env={}", e));
@@ -263,7 +552,25 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
 
     resolve_capabilities(&cli_args, &mut cmd);
 
-    mount_additional_mounts(ws_dir.as_path(), &cli_args, &mut cmd)?;
+    resolve_no_new_privileges(&cli_args, &mut cmd);
+
+    // generated profile is only needed until the container is created, it is removed on drop
+    let generated_seccomp_profile = resolve_seccomp(&ctx, &cli_args, &container_name, &mut cmd)?;
+
+    let (mount_args, mounts_mapping) = additional_mounts_args(
+        &ctx,
+        ws_dir.as_path(),
+        Path::new(&main_project_dir),
+        Path::new(&container_home),
+        &mut cli_args,
+    )?;
+
+    cmd.args(mount_args);
+    cmd.arg(format!(
+        "--label={}={}",
+        crate::CONTAINER_LABEL_MOUNTS,
+        serde_json::to_string(&mounts_mapping)?
+    ));
 
     {
         // find all terminfo dirs, they differ mostly on debian...
@@ -275,12 +582,35 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
     for (vol, path) in persist.iter().chain(persist_user.iter()) {
         // using mount here to prevent mounting paths from persist, either by accident or
         // intentionally
+        //
+        // NOTE: named volumes are labeled by the engine itself so they can be shared between
+        // containers, no relabeling required
         cmd.arg(format!(
             "--mount=type=volume,source={},destination={}",
             vol, path
         ));
     }
 
+    if cli_args.cache_volume.unwrap_or(false) {
+        cmd.arg(format!(
+            "--mount=type=volume,source={},destination={}/.cache",
+            cache_volume, container_home
+        ));
+    }
+
+    let packages_script = packages_script(&cli_args.packages, &packages_override);
+    if packages_script.is_some() {
+        cmd.arg(format!(
+            "--mount=type=volume,source={},destination={}",
+            package_cache_volume(&container_image),
+            crate::PACKAGE_CACHE_DIR
+        ));
+
+        if !cli_args.network.unwrap_or(false) {
+            log::warn!("Network is disabled so packages can only be installed from the cache");
+        }
+    }
+
     // set network if requested
     if !cli_args.network.unwrap_or(false) {
         cmd.arg("--network=none");
@@ -290,6 +620,9 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
 
     mount_wayland(&ctx, &cli_args, &mut cmd)?;
 
+    // the cookie is only needed until the container is created, it is removed on drop
+    let generated_xauthority = mount_x11(&ctx, &cli_args, &container_name, &mut cmd)?;
+
     mount_ssh_agent(&ctx, &cli_args, &mut cmd)?;
 
     mount_session_bus(&ctx, &cli_args, &mut cmd)?;
@@ -301,11 +634,46 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
         cmd.arg(format!("--publish={}:{}/udp", host, container));
     }
 
-    // mount skel if provided
-    if let Some(skel) = &cli_args.skel {
-        cmd.arg(format!("--volume={}:/etc/skel:ro", skel));
+    // the variables may contain host env values so they are not passed as an env var, the file
+    // is removed on drop
+    let generated_skel_vars =
+        mount_skel_vars(&ctx, &template_vars, &container_name, selinux, &mut cmd)?;
+
+    // mount skel layers in order, they are applied over the image /etc/skel
+    for (index, skel) in cli_args.skel.iter().enumerate() {
+        cmd.arg(format!(
+            "--volume={}:{}/{:02}{}",
+            skel,
+            crate::SKEL_DIR,
+            index,
+            volume_options(selinux, &["ro"])
+        ));
     }
 
+    // record the settings so the config can be recreated from the container
+    let mut effective = effective_config(
+        &cli_args,
+        &container_image,
+        &persist,
+        &persist_user,
+        &on_init_pre,
+        &on_init_post,
+    );
+    effective.packages_override = packages_override;
+
+    cmd.arg(format!(
+        "--label={}={}",
+        crate::CONTAINER_LABEL_CONFIG,
+        serde_json::to_string(&effective)?
+    ));
+
+    // make sure extra args do not silently undo the sandbox
+    let managed_args = cmd
+        .get_args()
+        .map(|x| x.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    policy.check_engine_args(&managed_args, &cli_args.engine_args)?;
+
     // add the extra args verbatim
     cmd.args(cli_args.engine_args.clone());
 
@@ -325,9 +693,23 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
 
         Ok(())
     } else {
+        // secrets are created as late as possible so they are not left behind on errors
+        let secrets = create_secrets(&ctx, &cli_args, &container_name)?;
+
         // do i need stdout if it fails?
         let output = cmd.log_output().expect(crate::ENGINE_ERR_MSG);
 
+        // the container keeps its own copy
+        remove_secrets(&ctx, &secrets);
+
+        // the container keeps its own copy of the generated files
+        drop(generated_seccomp_profile);
+        drop(generated_env_file);
+
+        // the container keeps the mounted file even after it is removed
+        drop(generated_xauthority);
+        drop(generated_skel_vars);
+
         if !output.status.success() {
             return Err(anyhow!(
                 "Stderr from container init: {}",
@@ -338,61 +720,22 @@ pub fn start_container(ctx: Context, mut cli_args: CmdStartArgs) -> Result<()> {
         let id = String::from_utf8_lossy(&output.stdout);
         let id = id.trim();
 
-        // check if file exists in the container, used for flag files
-        let container_file_exists = |file: &str| -> Result<bool> {
-            log::trace!("Testing for existance of {file:?}");
-
-            let cmd = ctx
-                .engine
-                .command()
-                .args(["exec", id, "test", "-f", file])
-                .log_output()
-                .expect(crate::ENGINE_ERR_MSG);
-
-            match cmd.get_code() {
-                0 => Ok(true),
-                1 => Ok(false),
-                125 => Err(anyhow!("Container has exited unexpectedly (125)")),
-                127 => panic!("Unknown command used during container initialization check"),
-
-                // this really should not happen unless something breaks
-                x => Err(anyhow!(
-                    "Unknown error during container initialization ({x})"
-                )),
-            }
-        };
-
-        // write pre init script into the container
-        if !on_init_pre.is_empty() {
+        // packages are installed first so other scripts can use them
+        if let Some(script) = &packages_script {
             let path = PathBuf::new()
                 .join(crate::INIT_D_DIR)
-                .join("01_on_init_pre.sh");
+                .join(format!("00_packages{}", crate::INIT_D_ROOT_SUFFIX));
 
-            let buffer: String = "#!/bin/sh\nset -e\n".to_string() + &on_init_pre;
-            write_to_file(&ctx, id, &path, &buffer)?;
+            write_to_file(&ctx, id, &path, script)?;
         }
 
-        if !persist_user.is_empty() {
+        // write pre init script into the container
+        if !on_init_pre.is_empty() {
             let path = PathBuf::new()
                 .join(crate::INIT_D_DIR)
-                .join("00_chown_persist.sh");
-
-            // get each path
-            let persist_user_paths = persist_user
-                .iter()
-                .map(|(_, x)| x.clone())
-                .collect::<Vec<_>>()
-                .join(" ");
-
-            let buffer: String = format!(
-                r#"#!/bin/sh
-set -e
-
-asroot chown "$USER:$USER" {0}
-"#,
-                persist_user_paths
-            );
+                .join("01_on_init_pre.sh");
 
+            let buffer: String = "#!/bin/sh\nset -e\n".to_string() + &on_init_pre;
             write_to_file(&ctx, id, &path, &buffer)?;
         }
 
@@ -409,9 +752,7 @@ asroot chown "$USER:$USER" {0}
         log::trace!("Waiting for container preinitalization");
 
         // wait until container finishes pre-initialization
-        while !container_file_exists(crate::FLAG_FILE_PRE_INIT)? {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
+        wait_for_init(&ctx, id, &container_name, InitPhase::Waiting)?;
 
         // remove pre-init flag to start initalization
         ctx.engine.exec(id, &["rm", crate::FLAG_FILE_PRE_INIT])?;
@@ -419,9 +760,7 @@ asroot chown "$USER:$USER" {0}
         log::trace!("Waiting for container initialization");
 
         // wait until container finishes initialization
-        while !container_file_exists(crate::FLAG_FILE_INIT)? {
-            std::thread::sleep(std::time::Duration::from_millis(300));
-        }
+        wait_for_init(&ctx, id, &container_name, InitPhase::Finished)?;
 
         if cli_args.enter {
             log::debug!("Launching shell");
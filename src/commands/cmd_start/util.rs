@@ -1,33 +1,12 @@
 use crate::cli::CmdStartArgs;
 use crate::command_extensions::*;
+use crate::commands::cmd_init::skel;
+use crate::commands::cmd_init::status::{InitPhase, InitStatus};
+use crate::config::{Config, Mount, PortMapping, PrivilegeMode, SELinuxMode, SecretSource};
 use crate::prelude::*;
-use crate::APP_NAME;
-use std::collections::HashMap;
-use std::path::Path;
-
-/// Get hostname from system using `hostname` command
-pub fn get_hostname() -> Result<String> {
-    // try to get hostname from env var
-    if let Ok(env_hostname) = std::env::var("HOSTNAME") {
-        log::debug!("Getting hostname from environment");
-        return Ok(env_hostname);
-    }
-
-    log::debug!("Getting hostname using hostname command");
-
-    // then as a fallback use hostname executable
-    let cmd = Command::new("hostname")
-        .log_output()
-        .with_context(|| "Could not call hostname")?;
-
-    let hostname = String::from_utf8_lossy(&cmd.stdout);
-
-    if !cmd.status.success() || hostname.is_empty() {
-        return Err(anyhow!("Unable to get hostname from host"));
-    }
-
-    Ok(hostname.trim().into())
-}
+use crate::{PathMapping, APP_NAME, FULL_VERSION};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
 /// Generates random name using adjectives list
 pub fn generate_name() -> String {
@@ -165,6 +144,315 @@ pub fn resolve_capabilities(cli_args: &CmdStartArgs, cmd: &mut Command) {
     }
 }
 
+/// Adds `no-new-privileges` security option, it is always set with privilege mode `none` so
+/// setuid binaries cannot be used to gain root privileges anyway
+pub fn resolve_no_new_privileges(cli_args: &CmdStartArgs, cmd: &mut Command) {
+    if cli_args.no_new_privileges.unwrap_or(false)
+        || cli_args.privilege.unwrap_or_default() == PrivilegeMode::None
+    {
+        cmd.arg("--security-opt=no-new-privileges");
+    }
+}
+
+/// Built-in groups of syscalls that can be denied
+pub const SYSCALL_GROUPS: &[(&str, &[&str])] = &[
+    (
+        "ptrace",
+        &["ptrace", "process_vm_readv", "process_vm_writev", "kcmp"],
+    ),
+    (
+        "mount",
+        &[
+            "mount",
+            "umount",
+            "umount2",
+            "pivot_root",
+            "fsopen",
+            "fsconfig",
+            "fsmount",
+            "fspick",
+            "move_mount",
+            "open_tree",
+            "mount_setattr",
+        ],
+    ),
+    ("bpf", &["bpf"]),
+    ("keyring", &["add_key", "request_key", "keyctl"]),
+];
+
+/// Default seccomp profiles used by podman, in order of priority
+const DEFAULT_SECCOMP_PROFILES: &[&str] = &[
+    "/etc/containers/seccomp.json",
+    "/usr/share/containers/seccomp.json",
+];
+
+/// Resolve syscall groups into syscalls, groups are added or removed (with `!group`) in order
+pub fn resolve_syscall_groups(groups: &[String]) -> Result<Vec<&'static str>> {
+    // same as capabilities, true => deny, false => allow
+    let mut denied = HashMap::<&str, bool>::new();
+
+    for i in groups {
+        let (group, deny) = match i.strip_prefix("!") {
+            Some(x) => (x, false),
+            None => (i.as_str(), true),
+        };
+
+        if !SYSCALL_GROUPS.iter().any(|(name, _)| *name == group) {
+            return Err(anyhow!(
+                "Invalid syscall group {:?}, valid groups are: {}",
+                group,
+                SYSCALL_GROUPS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        denied.insert(group, deny);
+    }
+
+    // keep the order of the groups so the generated profile is deterministic
+    Ok(SYSCALL_GROUPS
+        .iter()
+        .filter(|(name, _)| denied.get(name).copied().unwrap_or(false))
+        .flat_map(|(_, syscalls)| syscalls.iter().copied())
+        .collect())
+}
+
+/// Removes syscalls from all allow rules in the seccomp profile, and explicitly denies them
+pub fn deny_syscalls_in_profile(profile: &mut serde_json::Value, syscalls: &[&str]) -> Result<()> {
+    use serde_json::{json, Value};
+
+    let Some(rules) = profile.get_mut("syscalls") else {
+        return Err(anyhow!("Invalid seccomp profile, missing \"syscalls\""));
+    };
+
+    let Value::Array(rules) = rules else {
+        return Err(anyhow!(
+            "Invalid seccomp profile, \"syscalls\" is not an array"
+        ));
+    };
+
+    for rule in rules.iter_mut() {
+        if rule.get("action").and_then(Value::as_str) != Some("SCMP_ACT_ALLOW") {
+            continue;
+        }
+
+        if let Some(Value::Array(names)) = rule.get_mut("names") {
+            names.retain(|x| !x.as_str().is_some_and(|x| syscalls.contains(&x)));
+        }
+    }
+
+    // remove rules that are now empty so they do not allow everything
+    rules.retain(|rule| match rule.get("names") {
+        Some(Value::Array(names)) => !names.is_empty(),
+        _ => true,
+    });
+
+    // explicitly deny in case the default action is to allow
+    rules.push(json!({
+        "names": syscalls,
+        "action": "SCMP_ACT_ERRNO",
+        "errnoRet": 1,
+    }));
+
+    Ok(())
+}
+
+/// File generated for the engine that is only needed until the container is created, it is
+/// removed when dropped so it is not left behind when start fails
+pub struct GeneratedFile {
+    path: PathBuf,
+    description: &'static str,
+}
+
+impl GeneratedFile {
+    pub fn new(path: PathBuf, description: &'static str) -> Self {
+        Self { path, description }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for GeneratedFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(_) => {}
+            // it may not have been written at all
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!(
+                "Failed to remove generated {} {:?}: {}",
+                self.description,
+                self.path,
+                err
+            ),
+        }
+    }
+}
+
+/// Passes the seccomp profile to the engine, if any syscalls are denied a new profile is generated
+/// from the custom (or default) profile
+///
+/// Returns the generated profile if one was generated
+pub fn resolve_seccomp(
+    ctx: &Context,
+    cli_args: &CmdStartArgs,
+    container_name: &str,
+    cmd: &mut Command,
+) -> Result<Option<GeneratedFile>> {
+    if let Some(profile) = &cli_args.seccomp_profile {
+        if !Path::new(profile).is_file() {
+            return Err(anyhow!("Seccomp profile {:?} does not exist", profile));
+        }
+    }
+
+    let syscalls = resolve_syscall_groups(&cli_args.deny_syscalls)?;
+    if syscalls.is_empty() {
+        // just use the custom profile as is
+        if let Some(profile) = &cli_args.seccomp_profile {
+            cmd.arg(format!("--security-opt=seccomp={}", profile));
+        }
+
+        return Ok(None);
+    }
+
+    let base_profile = match &cli_args.seccomp_profile {
+        Some(x) => PathBuf::from(x),
+        None => DEFAULT_SECCOMP_PROFILES
+            .iter()
+            .map(PathBuf::from)
+            .find(|x| x.exists())
+            .ok_or(anyhow!(
+                "Could not find the default seccomp profile, please set one explicitly"
+            ))?,
+    };
+
+    log::debug!("Generating seccomp profile from {base_profile:?} denying {syscalls:?}");
+
+    let mut profile: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(&base_profile)
+            .with_context(|| format!("while reading seccomp profile {:?}", base_profile))?,
+    )
+    .with_context(|| format!("while parsing seccomp profile {:?}", base_profile))?;
+
+    deny_syscalls_in_profile(&mut profile, &syscalls)
+        .with_context(|| format!("while generating seccomp profile from {:?}", base_profile))?;
+
+    let dir = ctx.get_local_state_dir().join("seccomp");
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory {:?}", dir))?;
+
+    let file = GeneratedFile::new(
+        dir.join(format!("{}.json", container_name)),
+        "seccomp profile",
+    );
+    std::fs::write(file.path(), serde_json::to_string_pretty(&profile)?)
+        .with_context(|| format!("Failed to write seccomp profile {:?}", file.path()))?;
+
+    cmd.arg(format!("--security-opt=seccomp={}", file.path().display()));
+
+    Ok(Some(file))
+}
+
+/// Formats volume options with SELinux relabeling option, returns empty string if there are no
+/// options
+pub fn volume_options(selinux: SELinuxMode, options: &[&str]) -> String {
+    let options = options
+        .iter()
+        .copied()
+        .chain(selinux.volume_option())
+        .collect::<Vec<_>>();
+
+    if options.is_empty() {
+        "".into()
+    } else {
+        format!(":{}", options.join(","))
+    }
+}
+
+/// Copies the executable into the state directory, so it can be relabeled without affecting the
+/// host
+pub fn copy_executable(ctx: &Context, executable: &Path) -> Result<PathBuf> {
+    let dir = ctx.get_local_state_dir().join("bin");
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory {:?}", dir))?;
+
+    let dest = dir.join(format!("{}-{}", APP_NAME, FULL_VERSION));
+
+    let source_meta = executable
+        .metadata()
+        .with_context(|| format!("Failed to read metadata of {:?}", executable))?;
+
+    // version is not bumped on every build so compare size and mtime as well, the copy gets the
+    // mtime of the source
+    let is_outdated = match dest.metadata() {
+        Ok(x) => x.len() != source_meta.len() || x.modified().ok() != source_meta.modified().ok(),
+        Err(_) => true,
+    };
+
+    if is_outdated {
+        log::debug!("Copying executable {executable:?} to {dest:?}");
+
+        // NOTE: copy then rename as the executable may be running in other containers
+        let tmp = dir.join(format!(".{}-{}", APP_NAME, rand::random::<u32>()));
+        std::fs::copy(executable, &tmp)
+            .with_context(|| format!("Failed to copy executable to {:?}", tmp))?;
+        std::fs::File::options()
+            .write(true)
+            .open(&tmp)
+            .and_then(|x| x.set_modified(source_meta.modified()?))
+            .with_context(|| format!("Failed to set mtime of {:?}", tmp))?;
+        std::fs::rename(&tmp, &dest)
+            .with_context(|| format!("Failed to copy executable to {:?}", dest))?;
+    }
+
+    Ok(dest)
+}
+
+/// Removes overlay directories of containers that are gone, kill removes them but containers
+/// can also exit on their own or be removed by the engine directly
+pub fn remove_stale_overlay_dirs(ctx: &Context) -> Result<()> {
+    let dir = ctx.get_local_state_dir().join("overlay");
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Ok(());
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if !ctx.engine.container_exists(&name)? {
+            log::debug!("Removing stale overlay directory {:?}", entry.path());
+            crate::commands::remove_overlay_dir(ctx, &entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates empty overlay directory for the workspace, removing any leftovers from previous
+/// container with the same name
+pub fn prepare_overlay_dir(ctx: &Context, container_name: &str) -> Result<PathBuf> {
+    let dir = ctx
+        .get_local_state_dir()
+        .join("overlay")
+        .join(container_name);
+
+    if dir.exists() {
+        log::debug!("Removing old overlay directory {dir:?}");
+        crate::commands::remove_overlay_dir(ctx, &dir)?;
+    }
+
+    for i in ["upper", "work"] {
+        std::fs::create_dir_all(dir.join(i))
+            .with_context(|| format!("Failed to create overlay directory {:?}", dir.join(i)))?;
+    }
+
+    Ok(dir)
+}
+
 pub fn mount_wayland(ctx: &Context, cli_args: &CmdStartArgs, cmd: &mut Command) -> Result<()> {
     // try to pass through wayland socket
     if cli_args.wayland.unwrap_or(false) {
@@ -176,11 +464,20 @@ pub fn mount_wayland(ctx: &Context, cli_args: &CmdStartArgs, cmd: &mut Command)
             if Path::new(&socket_path).exists() {
                 log::debug!("Found wayland socket at {socket_path:?}");
 
-                // TODO pass XDG_CURRENT_DESKTOP XDG_SESSION_TYPE
                 cmd.args([
                     format!("--volume={0}:{0}", socket_path),
                     format!("--env=WAYLAND_DISPLAY={}", wayland_display),
+                    // some apps only use wayland if the session says so
+                    format!(
+                        "--env=XDG_SESSION_TYPE={}",
+                        std::env::var("XDG_SESSION_TYPE").unwrap_or("wayland".into())
+                    ),
                 ]);
+
+                // used by portals and toolkits to pick the desktop specific behaviour
+                if let Ok(desktop) = std::env::var("XDG_CURRENT_DESKTOP") {
+                    cmd.arg(format!("--env=XDG_CURRENT_DESKTOP={}", desktop));
+                }
             } else {
                 return Err(anyhow!(
                     "Could not find the wayland socket {:?}",
@@ -224,37 +521,636 @@ pub fn mount_wayland(ctx: &Context, cli_args: &CmdStartArgs, cmd: &mut Command)
     Ok(())
 }
 
-pub fn mount_additional_mounts(
+/// Returns engine args for the additional mounts and pairs of host and container paths including
+/// the main project, mounts without destination are mounted inside the workspace
+pub fn additional_mounts_args(
+    ctx: &Context,
     ws_dir: &Path,
+    main_project_dir: &Path,
+    container_home: &Path,
+    cli_args: &mut CmdStartArgs,
+) -> Result<(Vec<String>, PathMapping)> {
+    let selinux = cli_args.selinux.unwrap_or_default();
+    let mut args: Vec<String> = vec![];
+    let mut mapping: PathMapping = vec![(
+        ctx.cwd.to_string_lossy().to_string(),
+        main_project_dir.to_string_lossy().to_string(),
+    )];
+
+    // host path for each container path, used to detect collisions
+    let mut destinations: BTreeMap<PathBuf, PathBuf> =
+        BTreeMap::from([(main_project_dir.to_path_buf(), ctx.cwd.clone())]);
+
+    for mount in cli_args.mount.iter_mut() {
+        let source = Path::new(&mount.source);
+        if !source.exists() {
+            return Err(anyhow!("Mount source {:?} does not exist", source));
+        }
+
+        // get the absolute path
+        let source = source
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve mount source {:?}", source))?;
+
+        let destination = match &mount.destination {
+            // relative destinations are relative to home inside the container
+            Some(x) => container_home.join(x.strip_prefix("~/").unwrap_or(x)),
+            None => {
+                if !source.is_dir() {
+                    return Err(anyhow!(
+                        "Mount source {:?} is not a directory, files require a destination",
+                        source
+                    ));
+                }
+
+                match &mount.alias {
+                    Some(alias) => ws_dir.join(alias),
+                    None => ws_dir.join(source.file_name().ok_or_else(|| {
+                        anyhow!("Mount source {:?} requires a destination or alias", source)
+                    })?),
+                }
+            }
+        };
+
+        if let Some(other) = destinations.get(&destination) {
+            return Err(anyhow!(
+                "Mounts {:?} and {:?} would both be mounted at {:?}, use `--mount SRC=ALIAS` or a destination to rename one",
+                other,
+                source,
+                destination
+            ));
+        }
+
+        log::debug!("Mounting {source:?} to {destination:?}");
+
+        args.push(format!(
+            "--volume={}:{}{}",
+            source.to_string_lossy(),
+            destination.to_string_lossy(),
+            volume_options(selinux, &mount.options())
+        ));
+
+        mapping.push((
+            source.to_string_lossy().to_string(),
+            destination.to_string_lossy().to_string(),
+        ));
+        destinations.insert(destination, source.clone());
+
+        // record resolved paths in the effective config
+        mount.source = source.to_string_lossy().to_string();
+    }
+
+    Ok((args, mapping))
+}
+
+/// Parses dotenv file into pairs, supports comments, `export` prefix, single quotes (literal) and
+/// double quotes (with `\"` and `\\` escapes)
+pub fn parse_dotenv(input: &str) -> Result<Vec<(String, String)>> {
+    let mut vars: Vec<(String, String)> = vec![];
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line
+            .strip_prefix("export ")
+            .map(str::trim_start)
+            .unwrap_or(line);
+
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(anyhow!("Expected NAME=VALUE on line {}", index + 1));
+        };
+
+        let name = name.trim();
+        if name.is_empty()
+            || name.starts_with(|x: char| x.is_ascii_digit())
+            || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+        {
+            return Err(anyhow!(
+                "Invalid variable name {:?} on line {}",
+                name,
+                index + 1
+            ));
+        }
+
+        let value = value.trim();
+        let value = if let Some(rest) = value.strip_prefix('\'') {
+            let Some(end) = rest.find('\'') else {
+                return Err(anyhow!("Unterminated quote on line {}", index + 1));
+            };
+
+            rest[..end].to_string()
+        } else if let Some(rest) = value.strip_prefix('"') {
+            let mut output = String::new();
+            let mut chars = rest.chars();
+            let mut closed = false;
+
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some(x @ ('"' | '\\')) => output.push(x),
+                        Some(x) => {
+                            output.push('\\');
+                            output.push(x);
+                        }
+                        None => break,
+                    },
+                    x => output.push(x),
+                }
+            }
+
+            if !closed {
+                return Err(anyhow!("Unterminated quote on line {}", index + 1));
+            }
+
+            output
+        } else {
+            // comments after unquoted value need whitespace before them
+            match value.find(" #") {
+                Some(end) => value[..end].trim_end(),
+                None => value,
+            }
+            .to_string()
+        };
+
+        vars.push((name.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+/// Reads all env files into a single file passed to the engine with `--env-file` so the values
+/// are not visible on the command line, returns the generated file which is removed on drop
+pub fn resolve_env_files(
+    ctx: &Context,
     cli_args: &CmdStartArgs,
+    container_name: &str,
     cmd: &mut Command,
-) -> Result<()> {
-    for m in &cli_args.mount {
-        let mount = Path::new(m);
-        if mount.exists() {
-            if !mount.is_dir() {
-                return Err(anyhow!("Mountpoint {:?} is not a directory", mount));
+) -> Result<Option<GeneratedFile>> {
+    if cli_args.env_file.is_empty() {
+        return Ok(None);
+    }
+
+    let mut buffer = String::new();
+    for file in &cli_args.env_file {
+        let input = std::fs::read_to_string(file)
+            .with_context(|| format!("while reading env file {:?}", file))?;
+
+        for (name, value) in
+            parse_dotenv(&input).with_context(|| format!("while parsing env file {:?}", file))?
+        {
+            buffer += &format!("{}={}\n", name, value);
+        }
+    }
+
+    let path = ctx
+        .get_local_state_dir()
+        .join("env")
+        .join(format!("{}.env", container_name));
+
+    cmd.arg(format!("--env-file={}", path.to_string_lossy()));
+
+    if ctx.dry_run {
+        log::debug!("Would write env file {path:?}");
+        return Ok(None);
+    }
+
+    write_private_file(path, buffer.as_bytes(), "env file").map(Some)
+}
+
+/// Writes file readable only by the user, returns guard which removes it on drop
+fn write_private_file(
+    path: PathBuf,
+    contents: &[u8],
+    description: &'static str,
+) -> Result<GeneratedFile> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {:?}", dir))?;
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to create {} {:?}", description, path))?;
+    let generated = GeneratedFile::new(path, description);
+
+    // mode is only used if the file did not exist
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {} {:?}", description, generated.path()))?;
+
+    Ok(generated)
+}
+
+/// Mounts the skel template variables as a file readable only by the user, returns the generated
+/// file which is removed on drop
+pub fn mount_skel_vars(
+    ctx: &Context,
+    vars: &BTreeMap<String, String>,
+    container_name: &str,
+    selinux: SELinuxMode,
+    cmd: &mut Command,
+) -> Result<Option<GeneratedFile>> {
+    let path = ctx
+        .get_local_state_dir()
+        .join("skel")
+        .join(format!("{}.json", container_name));
+
+    cmd.arg(format!(
+        "--volume={}:{}{}",
+        path.to_string_lossy(),
+        crate::SKEL_VARS_FILE,
+        volume_options(selinux, &["ro"])
+    ));
+
+    if ctx.dry_run {
+        log::debug!("Would write skel template variables {path:?}");
+        return Ok(None);
+    }
+
+    write_private_file(path, &serde_json::to_vec(vars)?, "skel template variables").map(Some)
+}
+
+/// Name of the engine secret for a container secret
+fn secret_id(container_name: &str, name: &str) -> String {
+    format!("{}-{}", container_name, name)
+}
+
+/// Returns args mounting the secrets as files readable only by the user in `/run/secrets`
+pub fn secret_args(ctx: &Context, cli_args: &CmdStartArgs, container_name: &str) -> Vec<String> {
+    cli_args
+        .secrets
+        .iter()
+        .map(|(name, _)| {
+            format!(
+                "--secret={},type=mount,target={},uid={},gid={},mode=0400",
+                secret_id(container_name, name),
+                name,
+                ctx.user_id,
+                ctx.user_gid
+            )
+        })
+        .collect()
+}
+
+/// Reads secret value on the host, trailing newline is removed from command output
+pub fn read_secret(source: &SecretSource) -> Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStringExt;
+    use std::process::Stdio;
+
+    match source {
+        SecretSource::File(path) => {
+            std::fs::read(path).with_context(|| format!("Failed to read secret file {:?}", path))
+        }
+        SecretSource::Command(command) => {
+            let output = Command::new("/bin/sh")
+                .args(["-c", command])
+                // allow the command to ask for a password
+                .stdin(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .log_output_redacted()?;
+
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Secret command {:?} failed with {}",
+                    command,
+                    output.status
+                ));
             }
 
-            // get the absolute path
-            let mount = mount.canonicalize().unwrap();
+            let mut value = output.stdout;
+            if value.last() == Some(&b'\n') {
+                value.pop();
+            }
 
-            log::debug!("Mounting additional mount {mount:?}");
+            Ok(value)
+        }
+        SecretSource::Env(var) => std::env::var_os(var)
+            .map(|x| x.into_vec())
+            .ok_or_else(|| anyhow!("Secret variable {:?} is not set", var)),
+    }
+}
 
-            cmd.arg(format!(
-                "--volume={}:{}/{}",
-                mount.to_string_lossy(),
-                ws_dir.to_string_lossy(),
-                mount.file_name().unwrap().to_string_lossy()
-            ));
+/// Creates the engine secrets used by `secret_args`, returns their names so they can be removed
+/// right after the container is created
+pub fn create_secrets(
+    ctx: &Context,
+    cli_args: &CmdStartArgs,
+    container_name: &str,
+) -> Result<Vec<String>> {
+    let mut created: Vec<String> = vec![];
+
+    for (name, source) in &cli_args.secrets {
+        let id = secret_id(container_name, name);
+
+        let result = read_secret(source)
+            .and_then(|value| ctx.engine.secret_create(&id, &value))
+            .with_context(|| format!("while creating secret {:?} from {}", name, source));
+
+        if let Err(err) = result {
+            remove_secrets(ctx, &created);
+            return Err(err);
+        }
+
+        created.push(id);
+    }
+
+    Ok(created)
+}
+
+/// Removes the engine secrets, failure is only logged as the container is already created
+pub fn remove_secrets(ctx: &Context, secrets: &[String]) {
+    if secrets.is_empty() {
+        return;
+    }
+
+    let secrets = secrets.iter().map(String::as_str).collect::<Vec<_>>();
+    if let Err(err) = ctx.engine.secret_remove(&secrets) {
+        log::warn!("Failed to remove secrets {secrets:?}: {err}");
+    }
+}
+
+/// Name of the volume used to cache packages, one per image as packages are not compatible
+/// between distros
+pub fn package_cache_volume(image: &str) -> String {
+    format!("{}-pkgcache-{}", APP_NAME, sanitize_volume_name(image))
+}
+
+/// Name of the volume used as `XDG_CACHE_HOME`, shared by containers of the same config or image
+pub fn cache_volume_name(name: &str) -> String {
+    format!("{}-cache-{}", APP_NAME, sanitize_volume_name(name))
+}
+
+/// Replaces characters not allowed in volume names
+fn sanitize_volume_name(name: &str) -> String {
+    name.chars()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || x == '-' || x == '.' {
+                x
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Generates init script that installs the packages using whichever package manager is found,
+/// returns `None` if there is nothing to install
+pub fn packages_script(
+    packages: &[String],
+    overrides: &BTreeMap<String, Vec<String>>,
+) -> Option<String> {
+    let packages_for = |manager: &str| {
+        overrides
+            .get(manager)
+            .map(Vec::as_slice)
+            .unwrap_or(packages)
+            .join(" ")
+    };
+
+    if crate::config::PACKAGE_MANAGERS
+        .iter()
+        .all(|x| packages_for(x).is_empty())
+    {
+        return None;
+    }
+
+    // each manager is told to keep downloaded packages in the cache volume, if the refresh fails
+    // the install is attempted with whatever is cached
+    let commands = [
+        (
+            "apt-get",
+            "apt",
+            r#"APT="apt-get -o Dir::Cache::Archives=$CACHE/archives -o Dir::State::Lists=$CACHE/lists -o APT::Keep-Downloaded-Packages=true"
+    mkdir -p "$CACHE/archives/partial" "$CACHE/lists/partial"
+    $APT update || echo "Failed to update package lists, using cached ones"
+    DEBIAN_FRONTEND=noninteractive $APT install -y --no-install-recommends $PACKAGES"#,
+        ),
+        (
+            "dnf",
+            "dnf",
+            r#"dnf install -y --setopt=keepcache=True --setopt=cachedir="$CACHE" $PACKAGES \
+        || dnf install -y --cacheonly --setopt=cachedir="$CACHE" $PACKAGES"#,
+        ),
+        (
+            "apk",
+            "apk",
+            r#"apk add --cache-dir "$CACHE" $PACKAGES \
+        || apk add --no-network --cache-dir "$CACHE" $PACKAGES"#,
+        ),
+        (
+            "pacman",
+            "pacman",
+            r#"pacman -Sy --noconfirm --needed --cachedir "$CACHE" $PACKAGES \
+        || pacman -S --noconfirm --needed --cachedir "$CACHE" $PACKAGES"#,
+        ),
+        (
+            "zypper",
+            "zypper",
+            r#"zypper --non-interactive modifyrepo --all --keep-packages
+    zypper --non-interactive --pkg-cache-dir "$CACHE" install $PACKAGES \
+        || zypper --non-interactive --no-refresh --pkg-cache-dir "$CACHE" install $PACKAGES"#,
+        ),
+    ];
+
+    let mut script = format!("#!/bin/sh\n# generated by {}\nset -e\n\n", APP_NAME);
+
+    for (index, (executable, manager, command)) in commands.iter().enumerate() {
+        let packages = packages_for(manager);
+
+        script += &format!(
+            "{} command -v {} >/dev/null; then\n",
+            if index == 0 { "if" } else { "elif" },
+            executable
+        );
+
+        if packages.is_empty() {
+            script += &format!("    echo \"No packages to install using {}\"\n", manager);
         } else {
-            return Err(anyhow!("Mountpoint {:?} does not exist", mount));
+            script += &format!(
+                "    PACKAGES=\"{}\"\n    CACHE=\"{}/{}\"\n    mkdir -p \"$CACHE\"\n    {}\n",
+                packages,
+                crate::PACKAGE_CACHE_DIR,
+                manager,
+                command
+            );
+        }
+    }
+
+    script += "else\n    echo \"Could not find a supported package manager\" >&2\n    exit 1\nfi\n";
+
+    Some(script)
+}
+
+/// Collects paths of all skel templates in the directory recursively
+fn find_skel_templates(dir: &Path, templates: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read skel {:?}", dir))? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            find_skel_templates(&path, templates)?;
+        } else if skel::template_destination(&path).is_some() {
+            templates.push(path);
         }
     }
 
     Ok(())
 }
 
+/// Renders the skel templates on the host the same way config is expanded, so errors are caught
+/// before the container is created
+///
+/// Returns the host environment variables the templates use, the init renders them only with the
+/// passed variables
+pub fn skel_template_vars(
+    layers: &[String],
+    vars: &BTreeMap<String, String>,
+    strict: bool,
+) -> Result<BTreeMap<String, String>> {
+    let host_vars = std::cell::RefCell::new(BTreeMap::new());
+    let getter = |input: &str| -> Option<String> {
+        match input {
+            "RAND" | "RANDOM" => Some(rand::random::<u32>().to_string()),
+            _ => vars.get(input).cloned().or_else(|| {
+                let value = std::env::var(input).ok()?;
+                host_vars
+                    .borrow_mut()
+                    .insert(input.to_string(), value.clone());
+
+                Some(value)
+            }),
+        }
+    };
+
+    for layer in layers.iter().map(Path::new).filter(|x| x.is_dir()) {
+        let mut templates = vec![];
+        find_skel_templates(layer, &mut templates)?;
+
+        for template in templates {
+            let input = std::fs::read_to_string(&template)
+                .with_context(|| format!("Failed to read skel template {:?}", template))?;
+
+            crate::config::expand(&input, &getter, strict)
+                .with_context(|| format!("while rendering skel template {:?}", template))?;
+        }
+    }
+
+    Ok(host_vars.into_inner())
+}
+
+/// Returns `--env=NAME` for each host variable matching any of the patterns, engine takes the
+/// value from its own environment
+pub fn env_passthrough_args(patterns: &[String]) -> Vec<String> {
+    let mut names = std::env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| patterns.iter().any(|x| crate::glob_match(x, name)))
+        .collect::<Vec<_>>();
+
+    names.sort();
+
+    names.into_iter().map(|x| format!("--env={}", x)).collect()
+}
+
+/// Settings the container is started with as a config, used to recreate the config later
+///
+/// Env var values are omitted as labels are readable by anyone that can inspect the container,
+/// the values are read from the container env instead
+pub fn effective_config(
+    cli_args: &CmdStartArgs,
+    image: &str,
+    persist: &[(String, String)],
+    persist_user: &[(String, String)],
+    on_init_pre: &str,
+    on_init_post: &str,
+) -> Config {
+    // values are already expanded so escape them to be taken literally
+    let escape = |x: &String| x.replace('$', "$$");
+    let non_empty = |x: &str| (!x.is_empty()).then(|| escape(&x.to_string()));
+
+    Config {
+        image: escape(&image.to_string()),
+        skel: cli_args.skel.iter().map(escape).collect(),
+        skel_mode: cli_args.skel_mode.unwrap_or_default(),
+        shell: cli_args.shell.clone(),
+        workspace: cli_args.workspace.unwrap_or_default(),
+        network: cli_args.network.unwrap_or_default(),
+        pipewire: cli_args.pipewire.unwrap_or_default(),
+        pulseaudio: cli_args.pulseaudio.unwrap_or_default(),
+        wayland: cli_args.wayland.unwrap_or_default(),
+        x11: cli_args.x11.unwrap_or_default(),
+        ssh_agent: cli_args.ssh_agent.unwrap_or_default(),
+        session_bus: cli_args.session_bus.unwrap_or_default(),
+        persist: persist
+            .iter()
+            .map(|(k, v)| (escape(k), escape(v)))
+            .collect(),
+        persist_user: persist_user
+            .iter()
+            .map(|(k, v)| (escape(k), escape(v)))
+            .collect(),
+        cache_volume: cli_args.cache_volume.unwrap_or_default(),
+        packages: cli_args.packages.clone(),
+        on_init_pre: non_empty(on_init_pre),
+        on_init_post: non_empty(on_init_post),
+        ports: cli_args
+            .ports
+            .iter()
+            .map(|(container, host)| PortMapping::new(*container, *host))
+            .collect(),
+        // values may be secret so they are filled in from the container, passthrough vars are
+        // recorded the same way config new does
+        env: cli_args
+            .env
+            .iter()
+            .map(|x| match x.split_once('=') {
+                Some((k, _)) => (k.to_string(), String::new()),
+                None => (x.to_string(), format!("${{{}:-}}", x)),
+            })
+            .collect(),
+        env_file: cli_args.env_file.iter().map(escape).collect(),
+        env_passthrough: cli_args.env_passthrough.clone(),
+        secrets: cli_args
+            .secrets
+            .iter()
+            .map(|(name, source)| match source {
+                SecretSource::File(x) => (name.clone(), SecretSource::File(escape(x))),
+                x => (name.clone(), x.clone()),
+            })
+            .collect(),
+        capabilities: cli_args.capabilities.clone(),
+        no_new_privileges: cli_args.no_new_privileges.unwrap_or_default(),
+        seccomp_profile: cli_args.seccomp_profile.as_ref().map(escape),
+        deny_syscalls: cli_args.deny_syscalls.clone(),
+        selinux: cli_args.selinux.unwrap_or_default(),
+        privilege: cli_args.privilege.unwrap_or_default(),
+        mounts: cli_args
+            .mount
+            .iter()
+            .map(|x| Mount {
+                source: escape(&x.source),
+                destination: x.destination.as_ref().map(escape),
+                ..x.clone()
+            })
+            .collect(),
+        engine_args: cli_args.engine_args.iter().map(escape).collect(),
+        ..Default::default()
+    }
+}
+
 pub fn mount_audio(ctx: &Context, cli_args: &CmdStartArgs, cmd: &mut Command) -> Result<()> {
     // try to pass pipewire
     if cli_args.pipewire.unwrap_or(false) {
@@ -337,6 +1233,147 @@ pub fn mount_ssh_agent(ctx: &Context, cli_args: &CmdStartArgs, cmd: &mut Command
     Ok(())
 }
 
+/// Display number of a local X11 display (`:N`, `:N.S` or `unix:N`), remote displays return
+/// `None` as they cannot be mounted
+pub fn parse_x11_display(display: &str) -> Option<u32> {
+    let (host, display) = display.split_once(':')?;
+    if !host.is_empty() && host != "unix" {
+        return None;
+    }
+
+    let number = display.split_once('.').map(|(x, _)| x).unwrap_or(display);
+    number.parse().ok()
+}
+
+/// Replaces address family in `xauth nlist` output with wildcard so the cookie works regardless
+/// of the container hostname
+pub fn xauth_wildcard_family(nlist: &str) -> String {
+    nlist
+        .lines()
+        .filter(|x| x.len() > 4)
+        .map(|x| format!("ffff{}\n", &x[4..]))
+        .collect()
+}
+
+/// Generates untrusted Xauthority cookie for the display in a file only the container gets
+fn generate_xauthority(display: &str, path: &Path) -> Result<()> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let generated = GeneratedFile::new(path.with_extension("tmp"), "Xauthority");
+    let generated_str = generated.path().to_string_lossy();
+
+    // untrusted clients cannot snoop on other clients, timeout 0 means it does not expire
+    let output = Command::new("xauth")
+        .args(["-f", &generated_str, "generate", display, "."])
+        .args(["untrusted", "timeout", "0"])
+        .log_output_anyhow()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to generate Xauthority cookie: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let output = Command::new("xauth")
+        .args(["-f", &generated_str, "nlist"])
+        .log_output_anyhow()?;
+    drop(generated);
+
+    let mut child = Command::new("xauth")
+        .args(["-f", &path.to_string_lossy(), "nmerge", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .log_spawn_anyhow()?;
+
+    // stdin has to be closed so xauth knows the input ended
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(xauth_wildcard_family(&String::from_utf8_lossy(&output.stdout)).as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to write Xauthority cookie: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Mounts the X11 display socket with its own untrusted cookie, returns the generated cookie
+/// which is removed on drop
+pub fn mount_x11(
+    ctx: &Context,
+    cli_args: &CmdStartArgs,
+    container_name: &str,
+    cmd: &mut Command,
+) -> Result<Option<GeneratedFile>> {
+    if !cli_args.x11.unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let Ok(display) = std::env::var("DISPLAY") else {
+        return Err(anyhow!(
+            "Could not pass through X11 as DISPLAY is not defined"
+        ));
+    };
+
+    let Some(number) = parse_x11_display(&display) else {
+        return Err(anyhow!(
+            "Only local X11 displays can be passed through, DISPLAY is {:?}",
+            display
+        ));
+    };
+
+    let socket_path = format!("/tmp/.X11-unix/X{}", number);
+    if !Path::new(&socket_path).exists() {
+        return Err(anyhow!("Could not find the X11 socket {:?}", socket_path));
+    }
+
+    log::debug!("Found X11 socket at {socket_path:?}");
+
+    let dir = ctx.get_local_state_dir().join("x11");
+    let path = dir.join(format!("{}.xauth", container_name));
+    let xauthority = format!("/run/user/{}/Xauthority", ctx.user_id);
+
+    // the host ~/.Xauthority is never mounted, only the generated cookie
+    cmd.args([
+        format!("--volume={0}:{0}:ro", socket_path),
+        format!("--volume={}:{}:ro", path.to_string_lossy(), xauthority),
+        format!("--env=DISPLAY=:{}", number),
+        format!("--env=XAUTHORITY={}", xauthority),
+    ]);
+
+    if ctx.dry_run {
+        log::debug!("Would generate Xauthority cookie {path:?}");
+        return Ok(None);
+    }
+
+    if !crate::executable_in_path("xauth") {
+        return Err(anyhow!("Could not find xauth which is required for X11"));
+    }
+
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory {:?}", dir))?;
+
+    // xauth merges into existing files
+    if path.exists() {
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove old cookie {:?}", path))?;
+    }
+
+    let cookie = GeneratedFile::new(path, "Xauthority");
+    generate_xauthority(&format!(":{}", number), cookie.path())?;
+
+    Ok(Some(cookie))
+}
+
 pub fn mount_session_bus(ctx: &Context, cli_args: &CmdStartArgs, cmd: &mut Command) -> Result<()> {
     if cli_args.session_bus.unwrap_or(false) {
         if let Ok(dbus_addr) = std::env::var("DBUS_SESSION_BUS_ADDRESS") {
@@ -422,3 +1459,394 @@ pub fn write_to_file(ctx: &Context, container: &str, file: &Path, content: &str)
         ))
     }
 }
+
+/// Reads the init status from the container, returns `None` if the status was not written yet
+pub fn read_init_status(ctx: &Context, container: &str) -> Result<Option<InitStatus>> {
+    log::trace!("Reading init status");
+
+    let output = ctx
+        .engine
+        .command()
+        .args(["exec", container, "cat", crate::INIT_STATUS_FILE])
+        .log_output()
+        .expect(crate::ENGINE_ERR_MSG);
+
+    match output.get_code() {
+        0 => serde_json::from_slice(&output.stdout)
+            .map(Some)
+            .context("Failed to parse init status"),
+        1 => Ok(None),
+        125 => Err(anyhow!("Container has exited unexpectedly (125)")),
+
+        // this really should not happen unless something breaks
+        x => Err(anyhow!(
+            "Unknown error during container initialization ({x})"
+        )),
+    }
+}
+
+/// Waits until the init reaches `phase` or fails, progress is shown on stderr if it is a terminal
+pub fn wait_for_init(
+    ctx: &Context,
+    container: &str,
+    container_name: &str,
+    phase: InitPhase,
+) -> Result<()> {
+    use std::io::{IsTerminal, Write};
+
+    let tty = std::io::stderr().is_terminal();
+    let started = std::time::Instant::now();
+    let mut shown_steps = 0;
+
+    // clear the progress line
+    let clear = || {
+        if tty {
+            eprint!("\r\x1b[2K");
+        }
+    };
+
+    loop {
+        let status = read_init_status(ctx, container).inspect_err(|_| clear())?;
+
+        if let Some(status) = &status {
+            // print steps as they are completed
+            for step in status.steps.iter().skip(shown_steps) {
+                if tty {
+                    clear();
+                    eprintln!("  {}", step);
+                } else {
+                    log::debug!("Init step completed: {}", step);
+                }
+            }
+            shown_steps = status.steps.len();
+
+            if status.phase == InitPhase::Failed {
+                clear();
+
+                // the container exits once the status is removed
+                if let Err(err) = ctx
+                    .engine
+                    .exec(container, &["rm", "-f", crate::INIT_STATUS_FILE])
+                {
+                    log::warn!("Failed to acknowledge failed init: {err}");
+                }
+
+                return Err(anyhow!(
+                    "Container initialization failed at {:?}: {}\nSee the full output using `{} logs --init {}`",
+                    status.current.as_deref().unwrap_or_default(),
+                    status.error.as_deref().unwrap_or_default(),
+                    APP_NAME,
+                    container_name,
+                ));
+            }
+
+            if status.phase == phase || status.phase == InitPhase::Finished {
+                clear();
+                return Ok(());
+            }
+
+            if tty {
+                eprint!(
+                    "\r\x1b[2K{} ({}s)",
+                    status.current_step(),
+                    started.elapsed().as_secs()
+                );
+                let _ = std::io::stderr().flush();
+            }
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_file_removed_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.json");
+
+        std::fs::write(&path, "{}").unwrap();
+        drop(GeneratedFile::new(path.clone(), "test file"));
+        assert!(!path.exists());
+
+        // never written files are fine too
+        drop(GeneratedFile::new(path.clone(), "test file"));
+    }
+
+    #[test]
+    fn skel_vars_not_in_args() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ctx = Context::new(true, Box::new(crate::engine::Podman)).unwrap();
+        let vars = BTreeMap::from([("TOKEN".to_string(), "hunter2".to_string())]);
+
+        let mut cmd = Command::new("podman");
+        let generated =
+            mount_skel_vars(&ctx, &vars, "container", SELinuxMode::Disable, &mut cmd).unwrap();
+        assert!(generated.is_none());
+
+        let args = cmd
+            .get_args()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(args.len(), 1);
+        assert!(args[0].ends_with(&format!("skel/container.json:{}:ro", crate::SKEL_VARS_FILE)));
+        assert!(!args.iter().any(|x| x.contains("hunter2")));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("skel").join("container.json");
+        let generated =
+            write_private_file(path.clone(), &serde_json::to_vec(&vars).unwrap(), "vars").unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            r#"{"TOKEN":"hunter2"}"#
+        );
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        drop(generated);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn secret_read() {
+        assert_eq!(
+            read_secret(&SecretSource::Command("echo secret".into())).unwrap(),
+            b"secret"
+        );
+        assert!(read_secret(&SecretSource::Command("exit 1".into())).is_err());
+        assert!(read_secret(&SecretSource::Env("ARCAM_TEST_UNSET_SECRET".into())).is_err());
+        assert!(read_secret(&SecretSource::File("/nonexistent/secret".into())).is_err());
+    }
+
+    #[test]
+    fn packages_init_script() {
+        assert_eq!(packages_script(&[], &BTreeMap::new()), None);
+
+        let script = packages_script(
+            &["git".into(), "fd".into()],
+            &[("apt".into(), vec!["git".into(), "fd-find".into()])].into(),
+        )
+        .unwrap();
+
+        assert!(script.contains("PACKAGES=\"git fd-find\""), "{}", script);
+        assert!(script.contains("PACKAGES=\"git fd\""), "{}", script);
+        assert!(
+            script.contains(&format!("CACHE=\"{}/apk\"", crate::PACKAGE_CACHE_DIR)),
+            "{}",
+            script
+        );
+
+        // make sure the generated script is valid
+        let status = Command::new("sh")
+            .args(["-n", "-c", &script])
+            .status()
+            .unwrap();
+        assert!(status.success(), "{}", script);
+
+        assert_eq!(
+            package_cache_volume("docker.io/library/debian:latest"),
+            format!("{}-pkgcache-docker.io_library_debian_latest", APP_NAME)
+        );
+        assert_eq!(
+            cache_volume_name("rust dev"),
+            format!("{}-cache-rust_dev", APP_NAME)
+        );
+        assert!(crate::config::validate_package_name("python3-pip").is_ok());
+        assert!(crate::config::validate_package_name("git; rm -rf /").is_err());
+        assert!(crate::config::validate_package_name("--force").is_err());
+    }
+
+    #[test]
+    fn dotenv_parse() {
+        let vars = parse_dotenv(
+            r#"
+# comment
+EDITOR=nvim
+export TOKEN = abc # not part of the value
+SINGLE='$HOME # literal'
+DOUBLE="say \"hi\" \n"
+EMPTY=
+"#,
+        )
+        .unwrap();
+
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vars,
+            vec![
+                ("EDITOR", "nvim"),
+                ("TOKEN", "abc"),
+                ("SINGLE", "$HOME # literal"),
+                ("DOUBLE", "say \"hi\" \\n"),
+                ("EMPTY", ""),
+            ]
+        );
+
+        assert!(parse_dotenv("NOVALUE").is_err());
+        assert!(parse_dotenv("1VAR=x").is_err());
+        assert!(parse_dotenv("VAR=\"unterminated").is_err());
+    }
+
+    #[test]
+    fn effective_config_label() {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Wrapper {
+            #[command(flatten)]
+            args: CmdStartArgs,
+        }
+
+        let cli_args = Wrapper::parse_from([
+            "start",
+            "--network",
+            "-e",
+            "TOKEN=hunter2",
+            "-e",
+            "TERM",
+            "--port=80:8080",
+            "--mount=/data/$USER:.config/app:ro",
+            "fedora",
+            "--",
+            "--device=/dev/kvm",
+        ])
+        .args;
+
+        let config = effective_config(
+            &cli_args,
+            "fedora",
+            &[("cargo".into(), "/root/.cargo".into())],
+            &[],
+            "echo $HOME",
+            "",
+        );
+
+        let label = serde_json::to_string(&config).unwrap();
+        assert!(!label.contains("hunter2"), "{}", label);
+
+        let config = serde_json::from_str::<Config>(&label).unwrap();
+        assert!(config.network);
+        assert_eq!(config.on_init_pre.as_deref(), Some("echo $$HOME"));
+        assert_eq!(config.on_init_post, None);
+        assert_eq!(config.env.keys().collect::<Vec<_>>(), vec!["TERM", "TOKEN"]);
+        assert_eq!(config.env["TERM"], "${TERM:-}");
+        assert_eq!(config.env["TOKEN"], "");
+        assert_eq!(
+            config.mounts,
+            vec!["/data/$$USER:.config/app:ro".parse::<Mount>().unwrap()]
+        );
+        assert_eq!(config.engine_args, vec!["--device=/dev/kvm"]);
+    }
+
+    #[test]
+    fn no_new_privileges_forced() {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Wrapper {
+            #[command(flatten)]
+            args: CmdStartArgs,
+        }
+
+        let security_opts = |args: &[&str]| {
+            let cli_args = Wrapper::parse_from(["start"].iter().chain(args).chain(&["image"])).args;
+            let mut cmd = Command::new("podman");
+            resolve_no_new_privileges(&cli_args, &mut cmd);
+
+            cmd.get_args()
+                .map(|x| x.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(security_opts(&[]), Vec::<String>::new());
+        assert_eq!(
+            security_opts(&["--no-new-privileges"]),
+            vec!["--security-opt=no-new-privileges"]
+        );
+        assert_eq!(
+            security_opts(&["--privilege", "none"]),
+            vec!["--security-opt=no-new-privileges"]
+        );
+        assert_eq!(
+            security_opts(&["--privilege", "none", "--no-new-privileges=false"]),
+            vec!["--security-opt=no-new-privileges"]
+        );
+    }
+
+    #[test]
+    fn x11_display() {
+        assert_eq!(parse_x11_display(":0"), Some(0));
+        assert_eq!(parse_x11_display(":1.0"), Some(1));
+        assert_eq!(parse_x11_display("unix:2"), Some(2));
+        assert_eq!(parse_x11_display("localhost:10.0"), None);
+        assert_eq!(parse_x11_display(":abc"), None);
+        assert_eq!(parse_x11_display(""), None);
+
+        assert_eq!(
+            xauth_wildcard_family(
+                "0100 0002 766d 0001 30 0012 4d49542d4d414749432d434f4f4b49452d31 0010 00112233\n\n"
+            ),
+            "ffff 0002 766d 0001 30 0012 4d49542d4d414749432d434f4f4b49452d31 0010 00112233\n"
+        );
+    }
+
+    #[test]
+    fn volume_options_selinux() {
+        assert_eq!(volume_options(SELinuxMode::Disable, &[]), "");
+        assert_eq!(volume_options(SELinuxMode::Disable, &["ro"]), ":ro");
+        assert_eq!(volume_options(SELinuxMode::Private, &[]), ":Z");
+        assert_eq!(
+            volume_options(SELinuxMode::Shared, &["ro", "nocopy"]),
+            ":ro,nocopy,z"
+        );
+    }
+
+    #[test]
+    fn syscall_groups() {
+        let groups = |x: &[&str]| {
+            resolve_syscall_groups(&x.iter().map(|x| x.to_string()).collect::<Vec<_>>())
+        };
+
+        assert_eq!(groups(&["bpf"]).unwrap(), vec!["bpf"]);
+        assert!(groups(&["bpf", "!bpf"]).unwrap().is_empty());
+        assert_eq!(
+            groups(&["!bpf", "keyring", "bpf"]).unwrap(),
+            vec!["bpf", "add_key", "request_key", "keyctl"]
+        );
+        assert!(groups(&["invalid"]).is_err());
+    }
+
+    #[test]
+    fn seccomp_profile_deny() {
+        let mut profile = serde_json::json!({
+            "defaultAction": "SCMP_ACT_ERRNO",
+            "syscalls": [
+                { "names": ["read", "bpf"], "action": "SCMP_ACT_ALLOW" },
+                { "names": ["bpf"], "action": "SCMP_ACT_ALLOW", "includes": { "caps": ["CAP_SYS_ADMIN"] } },
+                { "names": ["bpf"], "action": "SCMP_ACT_LOG" },
+            ]
+        });
+
+        deny_syscalls_in_profile(&mut profile, &["bpf"]).unwrap();
+
+        assert_eq!(
+            profile["syscalls"],
+            serde_json::json!([
+                { "names": ["read"], "action": "SCMP_ACT_ALLOW" },
+                { "names": ["bpf"], "action": "SCMP_ACT_LOG" },
+                { "names": ["bpf"], "action": "SCMP_ACT_ERRNO", "errnoRet": 1 },
+            ])
+        );
+    }
+}
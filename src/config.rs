@@ -1,14 +1,439 @@
 //! Contains everything related to container configuration
 
+mod conditions;
+mod expand;
 mod v1;
+mod v2;
 use v1::ConfigV1;
+use v2::ConfigV2;
+
+pub use conditions::CONDITIONS_KEY;
+pub use expand::expand;
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 use std::path::Path;
+use std::str::FromStr;
 
 // Alias the latest config version
-pub type Config = ConfigV1;
+pub type Config = ConfigV2;
+
+/// SELinux labeling of the workspace and mounts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SELinuxMode {
+    /// Disable labeling for the container
+    #[default]
+    Disable,
+
+    /// Relabel the mounts so only this container can access them
+    Private,
+
+    /// Relabel the mounts so all containers can access them
+    Shared,
+}
+
+/// How the project directory is mounted in the container
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceMode {
+    /// Mount read-write
+    #[default]
+    Rw,
+
+    /// Mount read-only
+    Ro,
+
+    /// Mount with a copy-on-write overlay, all writes are discarded when container stops
+    Overlay,
+}
+
+/// How the user can gain root privileges inside the container
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivilegeMode {
+    /// Passwordless sudo for the user, falls back to passwordless su if sudo is missing
+    #[default]
+    Sudo,
+
+    /// Passwordless doas for the user
+    Doas,
+
+    /// No way to gain root privileges
+    None,
+}
+
+/// How files from skel layers outside the image are put into the user home
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SkelMode {
+    /// Copy the files, changes on the host are not reflected
+    #[default]
+    Copy,
+
+    /// Symlink the files so changes on the host show up live, templates are still rendered
+    Symlink,
+}
+
+/// List of skel layers, in config it is either a single path or a list of paths
+pub type SkelList = Vec<String>;
+
+/// Deserializes either a string or a list of strings
+pub fn string_or_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(x) => vec![x],
+        StringOrList::List(x) => x,
+    })
+}
+
+impl SELinuxMode {
+    /// Whether the container is labeled at all
+    pub fn is_enabled(&self) -> bool {
+        *self != Self::Disable
+    }
+
+    /// Option added to volumes to relabel them
+    pub fn volume_option(&self) -> Option<&'static str> {
+        match self {
+            Self::Disable => None,
+            Self::Private => Some("Z"),
+            Self::Shared => Some("z"),
+        }
+    }
+}
+
+/// Parses `PORT[:HOST_PORT]` into container and host port
+pub fn parse_port_mapping(input: &str) -> std::result::Result<(u32, u32), String> {
+    let parse_port = |raw: &str| -> std::result::Result<u32, String> {
+        raw.parse::<u32>()
+            .map_err(|_| format!("Invalid port {:?}", raw))
+    };
+
+    if let Some((left_raw, right_raw)) = input.split_once(":") {
+        Ok((parse_port(left_raw)?, parse_port(right_raw)?))
+    } else {
+        let port = parse_port(input)?;
+
+        // map it to itself
+        Ok((port, port))
+    }
+}
+
+/// Container port published on the host, in config it is either a port number or a string
+/// `PORT[:HOST_PORT]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "PortMappingRaw", into = "PortMappingRaw")]
+pub enum PortMapping {
+    Ports {
+        container: u32,
+        host: u32,
+    },
+
+    /// Contains variables so it can only be parsed after expansion
+    Template(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PortMappingRaw {
+    Port(u32),
+    Mapping(String),
+}
+
+impl PortMapping {
+    pub fn new(container: u32, host: u32) -> Self {
+        Self::Ports { container, host }
+    }
+
+    /// Expands the variables if any and returns container and host port
+    pub fn resolve<F>(&self, expand: F) -> Result<(u32, u32)>
+    where
+        F: Fn(&str) -> Result<String>,
+    {
+        match self {
+            Self::Ports { container, host } => Ok((*container, *host)),
+            Self::Template(x) => parse_port_mapping(&expand(x)?).map_err(anyhow::Error::msg),
+        }
+    }
+}
+
+impl Display for PortMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ports { container, host } if container == host => write!(f, "{}", container),
+            Self::Ports { container, host } => write!(f, "{}:{}", container, host),
+            Self::Template(x) => f.write_str(x),
+        }
+    }
+}
+
+impl FromStr for PortMapping {
+    type Err = String;
+
+    fn from_str(input: &str) -> std::result::Result<Self, Self::Err> {
+        if input.contains('$') {
+            return Ok(Self::Template(input.to_string()));
+        }
+
+        let (container, host) = parse_port_mapping(input)?;
+        Ok(Self::new(container, host))
+    }
+}
+
+impl TryFrom<PortMappingRaw> for PortMapping {
+    type Error = String;
+
+    fn try_from(value: PortMappingRaw) -> std::result::Result<Self, Self::Error> {
+        match value {
+            PortMappingRaw::Port(port) => Ok(Self::new(port, port)),
+            PortMappingRaw::Mapping(x) => x.parse(),
+        }
+    }
+}
+
+impl From<PortMapping> for PortMappingRaw {
+    fn from(value: PortMapping) -> Self {
+        match value {
+            PortMapping::Ports { container, host } if container == host => Self::Port(container),
+            x => Self::Mapping(x.to_string()),
+        }
+    }
+}
+
+/// Where the secret value is read from on the host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum SecretSource {
+    /// Contents of a file
+    File(String),
+
+    /// Output of a command ran using `/bin/sh` on the host
+    Command(String),
+
+    /// Value of a host environment variable
+    Env(String),
+}
+
+/// Host path mounted into the container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mount {
+    /// Path on the host, file or directory
+    pub source: String,
+
+    /// Absolute path or relative to the container home, when not set the directory is mounted
+    /// inside the workspace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+
+    /// Directory name inside the workspace used instead of the source basename, cannot be used
+    /// with destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub readonly: bool,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub noexec: bool,
+}
+
+impl Mount {
+    /// Checks if alias is a valid directory name and not combined with destination
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        let Some(alias) = &self.alias else {
+            return Ok(());
+        };
+
+        if self.destination.is_some() {
+            return Err(format!(
+                "Mount {:?} cannot have both destination and alias",
+                self.source
+            ));
+        }
+
+        if alias.is_empty() || alias == "." || alias == ".." || alias.contains('/') {
+            return Err(format!(
+                "Invalid mount alias {:?}, it must be a single directory name",
+                alias
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Options as passed to the engine
+    pub fn options(&self) -> Vec<&'static str> {
+        let mut options = vec![];
+
+        if self.readonly {
+            options.push("ro");
+        }
+
+        if self.noexec {
+            options.push("noexec");
+        }
+
+        options
+    }
+}
+
+impl Display for Mount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)?;
+
+        if let Some(alias) = &self.alias {
+            write!(f, "={}", alias)?;
+        }
+
+        // workspace mounts use empty destination to pass options
+        let options = self.options();
+        match &self.destination {
+            Some(destination) => write!(f, ":{}", destination)?,
+            None if !options.is_empty() => write!(f, ":")?,
+            None => {}
+        }
+
+        if !options.is_empty() {
+            write!(f, ":{}", options.join(","))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Mount {
+    type Err = String;
+
+    /// Parses `SOURCE[=ALIAS][::OPTIONS]` (mounted in workspace) or
+    /// `SOURCE:DESTINATION[:OPTIONS]` where options are comma separated `ro`, `rw`, `noexec` or
+    /// `exec`
+    fn from_str(input: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = input.splitn(3, ':');
+        let source = parts.next().unwrap_or_default();
+        let destination = parts.next();
+        let options = parts.next();
+
+        // empty destination is a workspace mount with options
+        let is_workspace = match destination {
+            None => true,
+            Some("") => options.is_some(),
+            Some(_) => false,
+        };
+
+        // alias is only allowed for workspace mounts
+        let (source, alias) = match source.rsplit_once('=') {
+            Some((source, alias)) if is_workspace => (source, Some(alias)),
+            _ => (source, None),
+        };
+
+        let mut mount = Self {
+            source: source.to_string(),
+            destination: destination.filter(|_| !is_workspace).map(str::to_string),
+            alias: alias.map(str::to_string),
+            readonly: false,
+            noexec: false,
+        };
+
+        if source.is_empty() || (destination.is_some_and(str::is_empty) && !is_workspace) {
+            return Err(format!(
+                "Invalid mount {:?}, expected SOURCE[=ALIAS][::OPTIONS] or SOURCE:DESTINATION[:OPTIONS]",
+                input
+            ));
+        }
+
+        mount.validate()?;
+
+        for option in options.into_iter().flat_map(|x| x.split(',')) {
+            match option {
+                "ro" => mount.readonly = true,
+                "rw" => mount.readonly = false,
+                "noexec" => mount.noexec = true,
+                "exec" => mount.noexec = false,
+                x => {
+                    return Err(format!(
+                        "Invalid mount option {:?}, expected one of ro, rw, noexec, exec",
+                        x
+                    ))
+                }
+            }
+        }
+
+        Ok(mount)
+    }
+}
+
+/// Package managers supported for installing `packages`, in order they are detected
+pub const PACKAGE_MANAGERS: [&str; 5] = ["apt", "dnf", "apk", "pacman", "zypper"];
+
+/// Checks if package name is safe to use in the generated script
+pub fn validate_package_name(name: &str) -> std::result::Result<(), String> {
+    if name.is_empty()
+        || name.starts_with('-')
+        || !name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || "._+-:@/=~".contains(x))
+    {
+        return Err(format!("Invalid package name {:?}", name));
+    }
+
+    Ok(())
+}
+
+/// Checks if secret name can be used as a file name in `/run/secrets`
+pub fn validate_secret_name(name: &str) -> std::result::Result<(), String> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || ['_', '-', '.'].contains(&x))
+    {
+        return Err(format!(
+            "Invalid secret name {:?}, only letters, numbers, '_', '-' and '.' are allowed",
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+impl Display for SecretSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(x) => write!(f, "file:{}", x),
+            Self::Command(x) => write!(f, "command:{}", x),
+            Self::Env(x) => write!(f, "env:{}", x),
+        }
+    }
+}
+
+impl FromStr for SecretSource {
+    type Err = String;
+
+    /// Parses `file:PATH`, `command:COMMAND` or `env:VAR`
+    fn from_str(input: &str) -> std::result::Result<Self, Self::Err> {
+        match input.split_once(':') {
+            Some(("file", x)) if !x.is_empty() => Ok(Self::File(x.to_string())),
+            Some(("command", x)) if !x.is_empty() => Ok(Self::Command(x.to_string())),
+            Some(("env", x)) if !x.is_empty() => Ok(Self::Env(x.to_string())),
+            _ => Err(format!(
+                "Invalid secret source {:?}, expected file:PATH, command:COMMAND or env:VAR",
+                input
+            )),
+        }
+    }
+}
 
 /// Config file with version string, use `Config` directly elsewhere
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -16,12 +441,15 @@ pub type Config = ConfigV1;
 pub enum ConfigFile {
     #[serde(rename = "1")]
     V01(ConfigV1),
+
+    #[serde(rename = "2")]
+    V02(ConfigV2),
 }
 
 impl ConfigFile {
     /// Construct latest version config
     pub fn latest(config: Config) -> Self {
-        Self::V01(config)
+        Self::V02(config)
     }
 }
 
@@ -30,14 +458,54 @@ impl TryInto<Config> for ConfigFile {
 
     fn try_into(self) -> std::result::Result<Config, Self::Error> {
         match self {
-            Self::V01(x) => Ok(x),
+            // older versions are upgraded automatically
+            Self::V01(x) => Ok(x.into()),
+            Self::V02(x) => Ok(x),
         }
     }
 }
 
 impl ConfigFile {
+    /// Parses config applying the matching conditional sections
     pub fn config_from_str(input: &str) -> Result<Config> {
-        toml::from_str::<ConfigFile>(input)?.try_into()
+        let mut table = toml::from_str::<toml::Table>(input)?;
+
+        // parse directly when possible as the errors contain the location
+        if !table.contains_key(CONDITIONS_KEY) {
+            return toml::from_str::<ConfigFile>(input)?.try_into();
+        }
+
+        let matched = conditions::apply_conditions(
+            &mut table,
+            &|x| std::env::var(x).ok(),
+            &crate::get_hostname,
+            &|x| Path::new(x).exists(),
+        )?;
+
+        let mut config: Config = toml::Value::Table(table)
+            .try_into::<ConfigFile>()
+            .context("while applying conditional sections")?
+            .try_into()?;
+
+        config.matched_conditions = matched;
+
+        Ok(config)
+    }
+
+    /// Parses config applying all conditional sections regardless if they match, so every section
+    /// is checked
+    pub fn config_from_str_all_conditions(input: &str) -> Result<Config> {
+        let mut table = toml::from_str::<toml::Table>(input)?;
+        let matched = conditions::apply_all_conditions(&mut table)?;
+
+        let mut config: Config = toml::Value::Table(table)
+            .try_into::<ConfigFile>()
+            .context("while applying conditional sections")?
+            .try_into()?;
+
+        config.matched_conditions = matched;
+
+        Ok(config)
     }
 
     pub fn config_from_file(file: &Path) -> Result<Config> {
@@ -87,4 +555,199 @@ engine_args = [ "default" ]
             }
         );
     }
+
+    #[test]
+    fn config_v2_syntax() {
+        let result = ConfigFile::config_from_str(
+            r#"
+version = "2"
+image = "fedora"
+ports = [ 3000, "80:8080", "${PORT:-80}" ]
+persist = { cargo = "~/.cargo" }
+
+[env]
+EDITOR = "nvim"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.ports,
+            vec![
+                PortMapping::new(3000, 3000),
+                PortMapping::new(80, 8080),
+                PortMapping::Template("${PORT:-80}".into())
+            ]
+        );
+        assert_eq!(result.env.get("EDITOR").map(String::as_str), Some("nvim"));
+        assert_eq!(
+            result.persist.get("cargo").map(String::as_str),
+            Some("~/.cargo")
+        );
+
+        // roundtrip keeps the short syntax
+        let serialized = toml::to_string(&ConfigFile::latest(result)).unwrap();
+        assert!(
+            serialized.contains(r#"ports = [3000, "80:8080", "${PORT:-80}"]"#),
+            "{}",
+            serialized
+        );
+
+        assert!(ConfigFile::config_from_str(
+            r#"
+version = "2"
+image = "fedora"
+ports = [ "http" ]
+"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn config_mounts() {
+        let result = ConfigFile::config_from_str(
+            r#"
+version = "2"
+image = "fedora"
+mounts = [
+    { source = "~/.gitconfig", destination = ".gitconfig", readonly = true },
+    { source = "../lib" },
+    { source = "../b/lib", alias = "lib-b" },
+]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.mounts,
+            vec![
+                "~/.gitconfig:.gitconfig:ro".parse::<Mount>().unwrap(),
+                "../lib".parse::<Mount>().unwrap(),
+                "../b/lib=lib-b".parse::<Mount>().unwrap(),
+            ]
+        );
+
+        let mount = "/data:/mnt/data:ro,noexec".parse::<Mount>().unwrap();
+        assert!(mount.readonly && mount.noexec);
+        assert_eq!(mount.to_string(), "/data:/mnt/data:ro,noexec");
+        assert_eq!(
+            "/data:/mnt/data:rw".parse::<Mount>().unwrap().to_string(),
+            "/data:/mnt/data"
+        );
+
+        assert_eq!(
+            "../b/lib=lib-b".parse::<Mount>().unwrap().to_string(),
+            "../b/lib=lib-b"
+        );
+
+        // workspace mounts with options
+        let mount = "../lib::ro".parse::<Mount>().unwrap();
+        assert!(mount.readonly && mount.destination.is_none() && mount.alias.is_none());
+        assert_eq!(mount.to_string(), "../lib::ro");
+
+        let mount = "../b/lib=lib-b::ro,noexec".parse::<Mount>().unwrap();
+        assert!(mount.readonly && mount.noexec);
+        assert_eq!(mount.alias.as_deref(), Some("lib-b"));
+        assert_eq!(mount.to_string(), "../b/lib=lib-b::ro,noexec");
+
+        assert_eq!(
+            Mount {
+                source: "../lib".into(),
+                destination: None,
+                alias: None,
+                readonly: true,
+                noexec: false,
+            }
+            .to_string(),
+            "../lib::ro"
+        );
+
+        assert!("/data:".parse::<Mount>().is_err());
+        assert!("/data::nodev".parse::<Mount>().is_err());
+        assert!("../lib=a/b".parse::<Mount>().is_err());
+        assert!("../lib=".parse::<Mount>().is_err());
+        assert!("/data:/mnt/data:nodev".parse::<Mount>().is_err());
+    }
+
+    #[test]
+    fn config_skel_layers() {
+        let single = ConfigFile::config_from_str(
+            r#"
+version = "2"
+image = "fedora"
+skel = "~/dotfiles"
+"#,
+        )
+        .unwrap();
+        assert_eq!(single.skel, vec!["~/dotfiles"]);
+        assert_eq!(single.skel_mode, SkelMode::Copy);
+
+        let layers = ConfigFile::config_from_str(
+            r#"
+version = "2"
+image = "fedora"
+skel = [ "team", "~/dotfiles" ]
+skel_mode = "symlink"
+"#,
+        )
+        .unwrap();
+        assert_eq!(layers.skel, vec!["team", "~/dotfiles"]);
+        assert_eq!(layers.skel_mode, SkelMode::Symlink);
+    }
+
+    #[test]
+    fn config_secrets() {
+        let result = ConfigFile::config_from_str(
+            r#"
+version = "2"
+image = "fedora"
+
+[secrets]
+github_token = { command = "pass show github" }
+npmrc = { file = "~/.npmrc" }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.secrets.get("github_token"),
+            Some(&SecretSource::Command("pass show github".into()))
+        );
+        assert_eq!(
+            "env:TOKEN".parse::<SecretSource>(),
+            Ok(SecretSource::Env("TOKEN".into()))
+        );
+        assert!("vault:x".parse::<SecretSource>().is_err());
+        assert!(validate_secret_name("../token").is_err());
+
+        assert!(ConfigFile::config_from_str(
+            r#"
+version = "2"
+image = "fedora"
+secrets = { token = { file = "a", env = "B" } }
+"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn config_v1_upgrade() {
+        let result = ConfigFile::config_from_str(
+            r#"
+version = "1"
+image = "fedora"
+ports = [ [ 80, 8080 ] ]
+env = [ [ "A", "1" ], [ "A", "2" ] ]
+persist = [ [ "cargo", "~/.cargo" ] ]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(result.ports, vec![PortMapping::new(80, 8080)]);
+        assert_eq!(result.env.get("A").map(String::as_str), Some("2"));
+        assert_eq!(
+            result.persist.get("cargo").map(String::as_str),
+            Some("~/.cargo")
+        );
+    }
 }
@@ -0,0 +1,215 @@
+//! Conditional config sections (`[when.KIND.CONDITION]`) merged over the base config
+
+use super::expand::{expand, ExpandError};
+use anyhow::{anyhow, Context, Result};
+use toml::{Table, Value};
+
+/// Key containing the conditional sections
+pub const CONDITIONS_KEY: &str = "when";
+
+/// Merges `overlay` into `base`, tables are merged recursively while everything else is replaced
+fn merge_table(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge_table(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Formats condition as it would be written in the config
+fn condition_label(kind: &str, condition: &str) -> String {
+    format!(
+        "{}.{}.{}",
+        CONDITIONS_KEY,
+        kind,
+        toml_edit::Key::new(condition).display_repr()
+    )
+}
+
+/// Removes the conditional sections from the table and merges the matching ones over it, returns
+/// the matched conditions in order they were applied
+///
+/// Kinds are always applied in order `env`, `hostname`, `exists` so the more specific conditions
+/// win, within a kind they are applied alphabetically
+pub fn apply_conditions<E, H, X>(
+    table: &mut Table,
+    env: &E,
+    hostname: &H,
+    exists: &X,
+) -> Result<Vec<String>>
+where
+    E: Fn(&str) -> Option<String>,
+    H: Fn() -> Result<String>,
+    X: Fn(&str) -> bool,
+{
+    // hostname is only needed if there are hostname conditions
+    let mut cached_hostname: Option<String> = None;
+
+    merge_conditions(table, &mut |kind, condition, label| {
+        Ok(match kind {
+            // either NAME is set and not empty or NAME=PATTERN
+            "env" => match condition.split_once('=') {
+                Some((name, pattern)) => env(name)
+                    .map(|x| crate::glob_match(pattern, &x))
+                    .unwrap_or(false),
+                None => env(condition).map(|x| !x.is_empty()).unwrap_or(false),
+            },
+            "hostname" => {
+                if cached_hostname.is_none() {
+                    cached_hostname = Some(hostname()?);
+                }
+
+                crate::glob_match(condition, cached_hostname.as_deref().unwrap())
+            }
+            "exists" => match expand(condition, env, true) {
+                Ok(path) => exists(&path),
+                // path cannot exist if its variable is not even set
+                Err(ExpandError::Undefined(_)) => false,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("while expanding {:?}", label))
+                }
+            },
+            _ => unreachable!(),
+        })
+    })
+}
+
+/// Removes the conditional sections from the table and merges all of them over it regardless if
+/// they match, used to check every section
+pub fn apply_all_conditions(table: &mut Table) -> Result<Vec<String>> {
+    merge_conditions(table, &mut |_, _, _| Ok(true))
+}
+
+/// Removes the conditional sections from the table and merges the ones `is_match` accepts, it is
+/// called with the kind, condition and label of each section
+fn merge_conditions(
+    table: &mut Table,
+    is_match: &mut dyn FnMut(&str, &str, &str) -> Result<bool>,
+) -> Result<Vec<String>> {
+    let Some(conditions) = table.remove(CONDITIONS_KEY) else {
+        return Ok(vec![]);
+    };
+
+    let Value::Table(mut conditions) = conditions else {
+        return Err(anyhow!("Key {:?} must be a table", CONDITIONS_KEY));
+    };
+
+    let mut matched: Vec<String> = vec![];
+
+    for kind in ["env", "hostname", "exists"] {
+        let Some(sections) = conditions.remove(kind) else {
+            continue;
+        };
+
+        let Value::Table(sections) = sections else {
+            return Err(anyhow!(
+                "Key \"{}.{}\" must be a table",
+                CONDITIONS_KEY,
+                kind
+            ));
+        };
+
+        for (condition, section) in sections {
+            let label = condition_label(kind, &condition);
+
+            let Value::Table(section) = section else {
+                return Err(anyhow!("Key {:?} must be a table", label));
+            };
+
+            if let Some(key) = ["version", CONDITIONS_KEY]
+                .into_iter()
+                .find(|x| section.contains_key(*x))
+            {
+                return Err(anyhow!("Key {:?} cannot be set in {:?}", key, label));
+            }
+
+            if is_match(kind, &condition, &label)? {
+                merge_table(table, section);
+                matched.push(label);
+            }
+        }
+    }
+
+    if let Some((kind, _)) = conditions.into_iter().next() {
+        return Err(anyhow!(
+            "Unknown condition kind \"{}.{}\", expected one of env, hostname, exists",
+            CONDITIONS_KEY,
+            kind
+        ));
+    }
+
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions_apply() {
+        let mut table = toml::from_str::<Table>(
+            r#"
+image = "fedora"
+engine_args = [ "base" ]
+env = { EDITOR = "nvim", TERM = "xterm" }
+
+[when.env.WAYLAND_DISPLAY]
+wayland = true
+
+[when.env."XDG_SESSION_TYPE=x*"]
+engine_args = [ "x11" ]
+
+[when.env.MISSING]
+network = true
+
+[when.hostname."build-*"]
+engine_args = [ "build" ]
+env = { EDITOR = "vi" }
+
+[when.exists."$HOME/kvm"]
+engine_args = [ "--device=/dev/kvm" ]
+"#,
+        )
+        .unwrap();
+
+        let env = |x: &str| match x {
+            "WAYLAND_DISPLAY" => Some("wayland-0".to_string()),
+            "XDG_SESSION_TYPE" => Some("x11".to_string()),
+            "HOME" => Some("/home/user".to_string()),
+            _ => None,
+        };
+        let hostname = || Ok("build-01".to_string());
+        let exists = |x: &str| x == "/home/user/kvm";
+
+        let matched = apply_conditions(&mut table, &env, &hostname, &exists).unwrap();
+
+        assert_eq!(
+            matched,
+            vec![
+                "when.env.WAYLAND_DISPLAY",
+                "when.env.\"XDG_SESSION_TYPE=x*\"",
+                "when.hostname.\"build-*\"",
+                "when.exists.\"$HOME/kvm\"",
+            ]
+        );
+        assert!(!table.contains_key("when"));
+        assert!(!table.contains_key("network"));
+        assert_eq!(table["wayland"], Value::Boolean(true));
+        assert_eq!(
+            table["engine_args"],
+            Value::Array(vec!["--device=/dev/kvm".into()])
+        );
+        assert_eq!(table["env"]["EDITOR"], Value::String("vi".into()));
+        assert_eq!(table["env"]["TERM"], Value::String("xterm".into()));
+
+        let no_hostname = || -> Result<String> { panic!("hostname should not be needed") };
+        let mut table = toml::from_str::<Table>("[when.shell.zsh]\nshell = \"zsh\"").unwrap();
+        assert!(apply_conditions(&mut table, &env, &no_hostname, &exists).is_err());
+
+        let mut table = toml::from_str::<Table>("[when.env.HOME]\nversion = \"1\"").unwrap();
+        assert!(apply_conditions(&mut table, &env, &no_hostname, &exists).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Expansion of environment variables in config values
+
+use std::fmt::Display;
+
+/// Error while expanding variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpandError {
+    /// Variable is not set
+    Undefined(String),
+
+    /// Variable is not set or empty but required using `${VAR:?message}`
+    Required { var: String, message: String },
+
+    /// Invalid syntax, contains the whole expression
+    Syntax(String),
+}
+
+impl Display for ExpandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Undefined(var) => write!(f, "Variable {:?} is not set", var),
+            Self::Required { var, message } => {
+                write!(f, "Variable {:?} is required: {}", var, message)
+            }
+            Self::Syntax(expr) => write!(f, "Invalid variable expression {:?}", expr),
+        }
+    }
+}
+
+impl std::error::Error for ExpandError {}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Expands `$VAR`, `${VAR}`, `${VAR:-default}` and `${VAR:?message}`, `$$` is a literal `$`
+///
+/// In strict mode undefined variables are an error, otherwise they are left unexpanded
+pub fn expand<F>(input: &str, getter: &F, strict: bool) -> Result<String, ExpandError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        // escaped dollar sign
+        if let Some(x) = rest.strip_prefix('$') {
+            output.push('$');
+            rest = x;
+            continue;
+        }
+
+        if let Some(inner) = rest.strip_prefix('{') {
+            // find the matching brace, defaults may contain variables as well
+            let mut depth = 1;
+            let end = inner.char_indices().find_map(|(i, c)| {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+
+                (depth == 0).then_some(i)
+            });
+
+            let Some(end) = end else {
+                return Err(ExpandError::Syntax(format!("${}", rest)));
+            };
+
+            let expr = &inner[..end];
+            let original = &rest[..end + 2];
+            rest = &inner[end + 1..];
+
+            let name_len = expr.find(|x| !is_name_char(x)).unwrap_or(expr.len());
+            let (name, modifier) = expr.split_at(name_len);
+
+            if name.is_empty() || name.starts_with(|x: char| x.is_ascii_digit()) {
+                return Err(ExpandError::Syntax(format!("${}", original)));
+            }
+
+            // empty values are treated as unset for both default and required
+            let value = getter(name).filter(|x| !x.is_empty() || modifier.is_empty());
+
+            if let Some(default) = modifier.strip_prefix(":-") {
+                match value {
+                    Some(x) => output.push_str(&x),
+                    None => output.push_str(&expand(default, getter, strict)?),
+                }
+            } else if let Some(message) = modifier.strip_prefix(":?") {
+                match value {
+                    Some(x) => output.push_str(&x),
+                    None => {
+                        return Err(ExpandError::Required {
+                            var: name.to_string(),
+                            message: message.to_string(),
+                        })
+                    }
+                }
+            } else if !modifier.is_empty() {
+                return Err(ExpandError::Syntax(format!("${}", original)));
+            } else {
+                match value {
+                    Some(x) => output.push_str(&x),
+                    None if strict => return Err(ExpandError::Undefined(name.to_string())),
+                    None => {
+                        log::warn!("Could not expand {name:?} in config");
+                        output.push('$');
+                        output.push_str(original);
+                    }
+                }
+            }
+
+            continue;
+        }
+
+        let name_len = rest.find(|x| !is_name_char(x)).unwrap_or(rest.len());
+        let name = &rest[..name_len];
+
+        // not a variable so keep it as is
+        if name.is_empty() || name.starts_with(|x: char| x.is_ascii_digit()) {
+            output.push('$');
+            continue;
+        }
+
+        rest = &rest[name_len..];
+
+        match getter(name) {
+            Some(x) => output.push_str(&x),
+            None if strict => return Err(ExpandError::Undefined(name.to_string())),
+            None => {
+                log::warn!("Could not expand {name:?} in config");
+                output.push('$');
+                output.push_str(name);
+            }
+        }
+    }
+
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_vars() {
+        let getter = |x: &str| match x {
+            "HOME" => Some("/home/user".to_string()),
+            "EMPTY" => Some("".to_string()),
+            _ => None,
+        };
+
+        let strict = |x: &str| expand(x, &getter, true);
+        let lenient = |x: &str| expand(x, &getter, false);
+
+        assert_eq!(strict("$HOME/.cargo").unwrap(), "/home/user/.cargo");
+        assert_eq!(strict("${HOME}_x").unwrap(), "/home/user_x");
+        assert_eq!(strict("$$HOME costs $5").unwrap(), "$HOME costs $5");
+        assert_eq!(strict("${EMPTY}").unwrap(), "");
+        assert_eq!(strict("${EMPTY:-def}").unwrap(), "def");
+        assert_eq!(strict("${PORT:-8080}").unwrap(), "8080");
+        assert_eq!(strict("${PORT:-${HOME}}").unwrap(), "/home/user");
+        assert_eq!(strict("${HOME:?must be set}").unwrap(), "/home/user");
+
+        assert_eq!(strict("$HOEM"), Err(ExpandError::Undefined("HOEM".into())));
+        assert_eq!(
+            strict("${TOKEN:?get one at example.com}"),
+            Err(ExpandError::Required {
+                var: "TOKEN".into(),
+                message: "get one at example.com".into()
+            })
+        );
+        assert!(matches!(strict("${HOME"), Err(ExpandError::Syntax(_))));
+        assert!(matches!(strict("${HOME:+x}"), Err(ExpandError::Syntax(_))));
+
+        assert_eq!(lenient("$HOEM/${HOEM}").unwrap(), "$HOEM/${HOEM}");
+        assert!(lenient("${TOKEN:?required}").is_err());
+    }
+}
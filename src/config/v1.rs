@@ -1,5 +1,6 @@
 //! Configuration version 1
 
+use super::{SELinuxMode, WorkspaceMode};
 use code_docs::{code_docs_struct, DocumentedStruct};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -35,6 +36,13 @@ code_docs_struct! {
         #[serde(default)]
         pub shell: Option<String>,
 
+        /// How the project directory is mounted, one of: rw, ro, overlay
+        ///
+        /// With `overlay` all writes go to a throwaway overlay, use `diff` command to inspect and
+        /// apply the changes
+        #[serde(default)]
+        pub workspace: WorkspaceMode,
+
         /// Set network access
         #[serde(default)]
         pub network: bool,
@@ -104,6 +112,30 @@ code_docs_struct! {
         #[serde(default)]
         pub capabilities: Vec<String>,
 
+        /// Prevent processes from gaining more privileges, breaks `sudo` and `su` inside the
+        /// container
+        #[serde(default)]
+        pub no_new_privileges: bool,
+
+        /// Path to custom seccomp profile (JSON), relative paths are relative to the config
+        ///
+        /// Environ vars are expanded
+        #[serde(default)]
+        pub seccomp_profile: Option<String>,
+
+        /// Deny groups of syscalls, or allow them with by prefixing `!group`
+        ///
+        /// Valid groups: ptrace, mount, bpf, keyring
+        #[serde(default)]
+        pub deny_syscalls: Vec<String>,
+
+        /// SELinux labeling, one of: disable, private, shared
+        ///
+        /// When not disabled the workspace, additional mounts and skel are relabeled, note that
+        /// passing through sockets may not work when labeling is enabled
+        #[serde(default)]
+        pub selinux: SELinuxMode,
+
         /// Args passed to the engine
         ///
         /// Environ vars are expanded
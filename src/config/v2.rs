@@ -0,0 +1,303 @@
+//! Configuration version 2
+
+use super::{
+    v1::ConfigV1, Mount, PortMapping, PrivilegeMode, SELinuxMode, SecretSource, SkelList, SkelMode,
+    WorkspaceMode,
+};
+use code_docs::{code_docs_struct, DocumentedStruct};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+// save all the fields and docs so they can be printed as always up-to-date documentation
+code_docs_struct! {
+    /// Single configuration for a container, contains default settings and optional settings per
+    /// engine that get applied over the default settings
+    #[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct ConfigV2 {
+        /// Path to the config
+        /// @skip
+        #[serde(skip)]
+        pub path: Option<PathBuf>,
+
+        /// Name of the config
+        /// @skip
+        #[serde(skip)]
+        pub name: Option<String>,
+
+        /// Conditional sections that were applied
+        /// @skip
+        #[serde(skip)]
+        pub matched_conditions: Vec<String>,
+
+        // --- real config options --- //
+
+        /// Image used for the container
+        pub image: String,
+
+        /// Directories with dotfiles copied into user home after the image `/etc/skel`, either a
+        /// path or a list of paths applied in order so later ones win
+        ///
+        /// Files ending with `.tmpl` are rendered with the same variables as the config, the
+        /// suffix is removed
+        ///
+        /// Environ vars are expanded
+        #[serde(default, deserialize_with = "super::string_or_list")]
+        pub skel: SkelList,
+
+        /// How the skel files are put into the home, one of: copy, symlink
+        #[serde(default)]
+        pub skel_mode: SkelMode,
+
+        /// Default user shell
+        #[serde(default)]
+        pub shell: Option<String>,
+
+        /// How the project directory is mounted, one of: rw, ro, overlay
+        ///
+        /// With `overlay` all writes go to a throwaway overlay, use `diff` command to inspect and
+        /// apply the changes
+        #[serde(default)]
+        pub workspace: WorkspaceMode,
+
+        /// Set network access
+        #[serde(default)]
+        pub network: bool,
+
+        /// Passthrough pipewire
+        #[serde(default)]
+        pub pipewire: bool,
+
+        /// Passthrough pulseaudio
+        #[serde(default)]
+        pub pulseaudio: bool,
+
+        /// Passthrough wayland compositor socket, high security impact, allows clipboard access
+        #[serde(default)]
+        pub wayland: bool,
+
+        /// Passthrough X11 display socket with an untrusted cookie, high security impact, allows
+        /// clipboard access
+        #[serde(default)]
+        pub x11: bool,
+
+        /// Passthrough ssh-agent socket, security impact is unknown
+        #[serde(default)]
+        pub ssh_agent: bool,
+
+        /// Passthrough D-BUS session bus, maximum security impact allows arbitrary code execution
+        #[serde(default)]
+        pub session_bus: bool,
+
+        /// Paths to mount as volumes (volume name = path), basically shorthand for
+        /// `--volume=<name>:<path>`
+        ///
+        /// Example: `persist = { cargo = "~/.cargo" }`
+        #[serde(default)]
+        pub persist: BTreeMap<String, String>,
+
+        /// Same as `persist` but the path is chowned as user on init
+        #[serde(default)]
+        pub persist_user: BTreeMap<String, String>,
+
+        /// Host files or directories mounted into the container, relative sources are relative to
+        /// the config, relative destinations to the container home
+        ///
+        /// Without a destination the directory is mounted inside the workspace under its basename,
+        /// or under `alias` if set
+        ///
+        /// Example: `mounts = [ { source = "~/.gitconfig", destination = ".gitconfig", readonly =
+        /// true } ]`
+        #[serde(default)]
+        pub mounts: Vec<Mount>,
+
+        /// Use a named volume as `XDG_CACHE_HOME` (`~/.cache`) shared between containers of this
+        /// config so caches survive restarts
+        #[serde(default)]
+        pub cache_volume: bool,
+
+        /// Packages installed on init using the detected package manager, one of: apt, dnf, apk,
+        /// pacman, zypper
+        ///
+        /// Example: `packages = [ "git", "ripgrep" ]`
+        ///
+        /// Downloaded packages are cached in a volume per image so restarts work offline
+        #[serde(default)]
+        pub packages: Vec<String>,
+
+        /// Packages used instead of `packages` for a specific package manager
+        ///
+        /// Example: `packages_override = { apt = [ "git", "fd-find" ], dnf = [ "git", "fd-find" ] }`
+        #[serde(default)]
+        pub packages_override: BTreeMap<String, Vec<String>>,
+
+        /// Run command before all other scripts (ran using `/bin/sh`)
+        #[serde(default)]
+        pub on_init_pre: Option<String>,
+
+        /// Run command after all other scripts (ran using `/bin/sh`)
+        #[serde(default)]
+        pub on_init_post: Option<String>,
+
+        // TODO make this into a command so any kind of script/executable could
+        // be used like python for example
+        /// Runs following shell script and pass all arguments verbatim to it,
+        /// script itself is responsible for running arcam start with all the arguments
+        ///
+        /// This allows you total control of the container startup which also
+        /// makes it dangerous if you do not check the config file beforhand
+        ///
+        /// NOTE: the script is ran using "/bin/sh"
+        pub host_pre_init: Option<String>,
+
+        /// Pass through container port to host (both TCP and UDP), either a port number or a
+        /// string `PORT[:HOST_PORT]` same as the cli
+        ///
+        /// Example: `ports = [ 3000, "80:8080" ]`
+        ///
+        /// Not all ports are allowed with rootless podman
+        #[serde(default)]
+        pub ports: Vec<PortMapping>,
+
+        /// Environment variables to set
+        ///
+        /// Example: `env = { EDITOR = "nvim" }`
+        ///
+        /// Environ vars are expanded
+        #[serde(default)]
+        pub env: BTreeMap<String, String>,
+
+        /// Dotenv files to read environment variables from, relative to the config directory
+        ///
+        /// Example: `env_file = [ "./.env", "~/secrets/dev.env" ]`
+        ///
+        /// Paths are expanded but the values are taken literally, they are passed to the engine
+        /// using a temporary file so they do not show up on the command line
+        #[serde(default)]
+        pub env_file: Vec<String>,
+
+        /// Forward host environment variables whose name matches any of the patterns, supports `*`
+        /// and `?` wildcards
+        ///
+        /// Example: `env_passthrough = [ "AWS_PROFILE", "GITHUB_*" ]`
+        #[serde(default)]
+        pub env_passthrough: Vec<String>,
+
+        /// Secrets mounted as files in `/run/secrets/NAME`, source is one of `file` (relative to
+        /// the config directory), `command` (ran using `/bin/sh` on the host) or `env`
+        ///
+        /// Example: `secrets = { github_token = { command = "pass show github" } }`
+        ///
+        /// Values never appear on the command line or in `inspect`, only file paths are expanded
+        #[serde(default)]
+        pub secrets: BTreeMap<String, SecretSource>,
+
+        /// Leave undefined variables unexpanded instead of failing, configs upgraded from
+        /// version 1 have this enabled to keep the old behaviour
+        ///
+        /// Variables are expanded in image, skel, persist, persist_user, mounts, ports, env,
+        /// env_file, on_init_pre, on_init_post, seccomp_profile and engine_args
+        ///
+        /// Supported syntax: `$VAR`, `${VAR}`, `${VAR:-default}`, `${VAR:?error message}`, use
+        /// `$$` for a literal `$`
+        #[serde(default)]
+        pub ignore_missing_env: bool,
+
+        /// Add capabilities, or drop them with by prefixing `!cap`
+        ///
+        /// For more details about capabilities read `man 7 capabilities`
+        #[serde(default)]
+        pub capabilities: Vec<String>,
+
+        /// Prevent processes from gaining more privileges, breaks `sudo` and `su` inside the
+        /// container, always enabled with privilege mode `none`
+        #[serde(default)]
+        pub no_new_privileges: bool,
+
+        /// Path to custom seccomp profile (JSON), relative paths are relative to the config
+        ///
+        /// Environ vars are expanded
+        #[serde(default)]
+        pub seccomp_profile: Option<String>,
+
+        /// Deny groups of syscalls, or allow them with by prefixing `!group`
+        ///
+        /// Valid groups: ptrace, mount, bpf, keyring
+        #[serde(default)]
+        pub deny_syscalls: Vec<String>,
+
+        /// SELinux labeling, one of: disable, private, shared
+        ///
+        /// When not disabled the workspace, additional mounts and skel are relabeled, note that
+        /// passing through sockets may not work when labeling is enabled
+        #[serde(default)]
+        pub selinux: SELinuxMode,
+
+        /// How the user can gain root privileges, one of: sudo, doas, none
+        ///
+        /// With `none` there is no escalation path inside the container, `asroot` fails as well
+        #[serde(default)]
+        pub privilege: PrivilegeMode,
+
+        /// Args passed to the engine
+        ///
+        /// Environ vars are expanded
+        #[serde(default)]
+        pub engine_args: Vec<String>,
+    }
+}
+
+impl ConfigV2 {
+    pub const VERSION: u32 = 2;
+}
+
+impl From<ConfigV1> for ConfigV2 {
+    fn from(value: ConfigV1) -> Self {
+        Self {
+            path: value.path,
+            name: value.name,
+            matched_conditions: vec![],
+            image: value.image,
+            skel: value.skel.into_iter().collect(),
+            skel_mode: SkelMode::default(),
+            shell: value.shell,
+            workspace: value.workspace,
+            network: value.network,
+            pipewire: value.pipewire,
+            pulseaudio: value.pulseaudio,
+            wayland: value.wayland,
+            x11: false,
+            ssh_agent: value.ssh_agent,
+            session_bus: value.session_bus,
+            // NOTE duplicate keys were allowed before, last one wins same as with env vars
+            persist: value.persist.into_iter().collect(),
+            persist_user: value.persist_user.into_iter().collect(),
+            mounts: vec![],
+            cache_volume: false,
+            // init commands were not expanded before
+            packages: vec![],
+            packages_override: BTreeMap::new(),
+            on_init_pre: value.on_init_pre.map(|x| x.replace('$', "$$")),
+            on_init_post: value.on_init_post.map(|x| x.replace('$', "$$")),
+            host_pre_init: value.host_pre_init,
+            ports: value
+                .ports
+                .into_iter()
+                .map(|(container, host)| PortMapping::new(container, host))
+                .collect(),
+            env: value.env.into_iter().collect(),
+            env_file: vec![],
+            env_passthrough: vec![],
+            secrets: BTreeMap::new(),
+            ignore_missing_env: true,
+            capabilities: value.capabilities,
+            no_new_privileges: value.no_new_privileges,
+            seccomp_profile: value.seccomp_profile,
+            deny_syscalls: value.deny_syscalls,
+            selinux: value.selinux,
+            privilege: PrivilegeMode::default(),
+            engine_args: value.engine_args,
+        }
+    }
+}
@@ -98,6 +98,11 @@ impl Context {
         Path::new("/etc/").join(APP_NAME).join("configs")
     }
 
+    /// Get path to the system-wide policy file
+    pub fn policy_file(&self) -> PathBuf {
+        Path::new("/etc/").join(APP_NAME).join("policy.toml")
+    }
+
     /// Get path to this executable
     pub fn get_executable_path(&self) -> Result<PathBuf> {
         std::env::current_exe().with_context(|| "Failed to get executable path")
@@ -1,5 +1,6 @@
 //! Engine specific abstraction
 
+pub mod args;
 mod podman;
 
 pub use podman::*;
@@ -12,6 +13,12 @@ use std::fmt::Display;
 pub struct ContainerInfo {
     pub name: String,
     pub labels: HashMap<String, String>,
+
+    /// Environment variables of the container, including ones set by the image
+    pub env: HashMap<String, String>,
+
+    /// Log driver used for the container output (ex. journald)
+    pub log_driver: String,
 }
 
 pub trait Engine: Display {
@@ -41,6 +48,12 @@ pub trait Engine: Display {
     /// Pull an image
     fn image_pull(&self, image: &str, interactive: bool) -> Result<()>;
 
+    /// Create a secret, value is passed through stdin so it does not appear on the command line
+    fn secret_create(&self, name: &str, value: &[u8]) -> Result<()>;
+
+    /// Remove secrets, containers already created keep their copy
+    fn secret_remove(&self, names: &[&str]) -> Result<()>;
+
     #[cfg(test)]
     fn start_dummy_container(
         &self,
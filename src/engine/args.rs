@@ -0,0 +1,212 @@
+//! Parsing of engine run arguments
+
+/// Flags that do not take a value unless passed as `--flag=value`
+const BOOL_FLAGS: &[&str] = &[
+    "--detach",
+    "--env-host",
+    "--init",
+    "--interactive",
+    "--no-healthcheck",
+    "--no-hosts",
+    "--privileged",
+    "--publish-all",
+    "--read-only",
+    "--read-only-tmpfs",
+    "--replace",
+    "--rm",
+    "--rmi",
+    "--tty",
+    "-it",
+];
+
+/// Short flags and aliases, mapped to the long flag
+const FLAG_ALIASES: &[(&str, &str)] = &[
+    ("-d", "--detach"),
+    ("-e", "--env"),
+    ("-h", "--hostname"),
+    ("-i", "--interactive"),
+    ("-l", "--label"),
+    ("-P", "--publish-all"),
+    ("-p", "--publish"),
+    ("-t", "--tty"),
+    ("-u", "--user"),
+    ("-v", "--volume"),
+    ("-w", "--workdir"),
+    ("--net", "--network"),
+];
+
+/// Flags that can only be set once, setting them again overrides the previous value
+const SINGLE_VALUE_FLAGS: &[&str] = &[
+    "--cgroupns",
+    "--detach-keys",
+    "--entrypoint",
+    "--hostname",
+    "--init",
+    "--ipc",
+    "--name",
+    "--network",
+    "--pid",
+    "--rm",
+    "--tz",
+    "--user",
+    "--userns",
+    "--uts",
+    "--workdir",
+];
+
+/// Flags that disable the sandbox regardless of any other flags
+const SANDBOX_BREAKING_FLAGS: &[&str] = &["--privileged"];
+
+/// Converts short flags and aliases into the long flag
+pub fn normalize_flag(flag: &str) -> &str {
+    FLAG_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == flag)
+        .map(|(_, long)| *long)
+        .unwrap_or(flag)
+}
+
+/// Splits args into flag value pairs, supports both `--flag=value` and `--flag value`
+///
+/// Flags are normalized into the long form, positional arguments are ignored
+pub fn parse_engine_args(args: &[String]) -> Vec<(String, Option<String>)> {
+    let mut parsed: Vec<(String, Option<String>)> = vec![];
+    let mut iter = args.iter().peekable();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("-") {
+            continue;
+        }
+
+        if let Some((flag, value)) = arg.split_once("=") {
+            parsed.push((normalize_flag(flag).to_string(), Some(value.to_string())));
+            continue;
+        }
+
+        let flag = normalize_flag(arg);
+        if BOOL_FLAGS.contains(&flag) {
+            parsed.push((flag.to_string(), None));
+        } else {
+            // take the next argument as the value if it is not a flag
+            let value = iter.next_if(|x| !x.starts_with("-")).cloned();
+            parsed.push((flag.to_string(), value));
+        }
+    }
+
+    parsed
+}
+
+/// Formats flag value pair back into a single argument
+pub fn format_arg(flag: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("{}={}", flag, value),
+        None => flag.to_string(),
+    }
+}
+
+/// Finds args in `extra` that conflict with or override args in `managed`
+///
+/// Returns the conflicting arg and the reason
+pub fn find_conflicts(managed: &[String], extra: &[String]) -> Vec<(String, String)> {
+    let managed = parse_engine_args(managed);
+    let mut conflicts: Vec<(String, String)> = vec![];
+
+    // returns managed arg that is overriden by this one
+    let overrides = |flag: &str, value: Option<&str>| -> Option<String> {
+        managed
+            .iter()
+            .filter(|(x, _)| x == flag)
+            .find(|(_, x)| x.as_deref() != value)
+            .map(|(x, y)| format_arg(x, y.as_deref()))
+    };
+
+    for (flag, value) in parse_engine_args(extra) {
+        let arg = format_arg(&flag, value.as_deref());
+
+        if SANDBOX_BREAKING_FLAGS.contains(&flag.as_str()) {
+            conflicts.push((arg, "disables the sandbox set up by arcam".into()));
+        } else if flag == "--security-opt" {
+            // security options only override same kind of option (ex. label=.., seccomp=..)
+            let value = value.unwrap_or_default();
+            let kind = value.split(['=', ':']).next().unwrap_or_default();
+
+            let overriden = managed
+                .iter()
+                .filter(|(x, _)| x == "--security-opt")
+                .filter_map(|(_, x)| x.as_deref())
+                .find(|x| x.split(['=', ':']).next() == Some(kind) && *x != value);
+
+            if let Some(overriden) = overriden {
+                conflicts.push((arg, format!("overrides \"--security-opt={}\"", overriden)));
+            }
+        } else if SINGLE_VALUE_FLAGS.contains(&flag.as_str()) {
+            if let Some(overriden) = overrides(&flag, value.as_deref()) {
+                conflicts.push((arg, format!("overrides {:?}", overriden)));
+            }
+        }
+    }
+
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_args_parse() {
+        let args = [
+            "--privileged",
+            "--pid",
+            "host",
+            "--net=host",
+            "-v",
+            "/:/host",
+            "image",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            parse_engine_args(&args),
+            vec![
+                ("--privileged".into(), None),
+                ("--pid".into(), Some("host".into())),
+                ("--network".into(), Some("host".into())),
+                ("--volume".into(), Some("/:/host".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn engine_args_conflicts() {
+        let managed = [
+            "run",
+            "--network=none",
+            "--security-opt=label=disable",
+            "--user=root",
+        ]
+        .map(String::from);
+
+        let extra = [
+            "--net",
+            "host",
+            "--user=root",
+            "--security-opt=label=type:x",
+            "--security-opt=no-new-privileges",
+            "--privileged",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            find_conflicts(&managed, &extra)
+                .into_iter()
+                .map(|(x, _)| x)
+                .collect::<Vec<_>>(),
+            vec![
+                "--network=host",
+                "--security-opt=label=type:x",
+                "--privileged"
+            ]
+        );
+    }
+}
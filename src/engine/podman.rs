@@ -10,6 +10,23 @@ use std::fmt::Display;
 pub struct PodmanContainerInfoConfig {
     #[serde(deserialize_with = "deserialize_null_default")]
     pub labels: HashMap<String, String>,
+
+    #[serde(deserialize_with = "deserialize_null_default")]
+    pub env: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PodmanContainerInfoLogConfig {
+    #[serde(default)]
+    pub r#type: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PodmanContainerInfoHostConfig {
+    #[serde(deserialize_with = "deserialize_null_default")]
+    pub log_config: PodmanContainerInfoLogConfig,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -17,6 +34,9 @@ pub struct PodmanContainerInfoConfig {
 pub struct PodmanContainerInfo {
     pub name: String,
     pub config: PodmanContainerInfoConfig,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub host_config: PodmanContainerInfoHostConfig,
 }
 
 impl From<PodmanContainerInfo> for ContainerInfo {
@@ -24,6 +44,14 @@ impl From<PodmanContainerInfo> for ContainerInfo {
         Self {
             name: value.name,
             labels: value.config.labels,
+            env: value
+                .config
+                .env
+                .iter()
+                .filter_map(|x| x.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            log_driver: value.host_config.log_config.r#type,
         }
     }
 }
@@ -144,6 +172,46 @@ impl Engine for Podman {
         Ok(())
     }
 
+    fn secret_create(&self, name: &str, value: &[u8]) -> Result<()> {
+        use std::io::Write;
+        use std::process::Stdio;
+
+        assert!(!name.is_empty());
+
+        let mut child = self
+            .command()
+            .args(["secret", "create", name, "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .log_spawn_anyhow()?;
+
+        // stdin has to be closed so podman knows the value ended
+        child.stdin.take().unwrap().write_all(value)?;
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to create secret {:?}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn secret_remove(&self, names: &[&str]) -> Result<()> {
+        assert!(!names.is_empty());
+
+        self.command()
+            .args(["secret", "rm"])
+            .args(names)
+            .log_output_anyhow()?;
+
+        Ok(())
+    }
+
     #[cfg(test)]
     fn start_dummy_container(
         &self,
@@ -202,7 +270,7 @@ mod tests {
     fn engine_inspect_podman() -> Result<()> {
         let obj = serde_json::from_str::<Vec<PodmanContainerInfo>>(INSPECT_OUTPUT)?;
         assert_eq!(
-            obj.first().take().unwrap(),
+            obj.first().unwrap(),
             &PodmanContainerInfo {
                 name: "wrathful-arcam".to_string(),
                 config: PodmanContainerInfoConfig {
@@ -222,6 +290,33 @@ mod tests {
                             "/mnt/slowmf/ws/projects/arcam".to_string()
                         ),
                     ]),
+                    env: [
+                        "LANG=en_US.UTF-8",
+                        "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+                        "XDG_RUNTIME_DIR=/run/user/1000",
+                        "ARCAM_VERSION=0.1.10",
+                        "NVIM_FORCE_OSC52=true",
+                        "HOST_USER_GID=1000",
+                        "TERMINFO_DIRS=/host/usr/share/terminfo:/host/etc/terminfo:/usr/share/terminfo:/etc/terminfo",
+                        "HOST_USER=sandorex",
+                        "HOST_USER_UID=1000",
+                        "LC_ALL=en_US.UTF-8",
+                        "CONTAINER_ENGINE=podman",
+                        "manager=podman",
+                        "RUSTUP_HOME=/opt/rustup",
+                        "container=oci",
+                        "arcam=arcam",
+                        "CONTAINER_NAME=wrathful-arcam",
+                        "HOSTNAME=thorium",
+                        "HOME=/root",
+                    ]
+                    .map(String::from)
+                    .to_vec(),
+                },
+                host_config: PodmanContainerInfoHostConfig {
+                    log_config: PodmanContainerInfoLogConfig {
+                        r#type: "journald".to_string(),
+                    },
                 },
             }
         );
@@ -4,6 +4,7 @@ mod commands;
 mod config;
 mod context;
 mod engine;
+mod policy;
 mod util;
 mod vars;
 
@@ -41,11 +42,9 @@ fn main() -> Result<()> {
     };
 
     // print parsed options
-    if cfg!(debug_assertions) {
-        if args.print_cmd {
-            dbg!(args);
-            return Ok(());
-        }
+    if cfg!(debug_assertions) && args.print_cmd {
+        dbg!(args);
+        return Ok(());
     }
 
     match args.cmd {
@@ -56,6 +55,7 @@ fn main() -> Result<()> {
         CliCommands::Config(x) => commands::config_command(get_ctx()?, x)?,
         CliCommands::List(x) => commands::print_containers(get_ctx()?, x)?,
         CliCommands::Logs(x) => commands::print_logs(get_ctx()?, x)?,
+        CliCommands::Diff(x) => commands::workspace_diff(get_ctx()?, x)?,
         CliCommands::Kill(x) => commands::kill_container(get_ctx()?, x)?,
         CliCommands::Completion(x) => {
             if x.complete.is_some() {
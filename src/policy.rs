@@ -0,0 +1,569 @@
+//! System-wide policy set by the administrator, user configs and cli cannot override it
+
+use crate::cli::CmdStartArgs;
+use crate::engine::args::{find_conflicts, format_arg, normalize_flag, parse_engine_args};
+use crate::util::glob_match;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What to do when a policy rule is violated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Ignore,
+    #[default]
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct EngineArgsPolicy {
+    /// What to do when engine args conflict with the args set by arcam
+    pub on_conflict: PolicyAction,
+
+    /// Engine args that are not allowed, either just the flag `--privileged` or flag with a value
+    /// `--network=host`
+    pub deny: Vec<String>,
+
+    /// If not empty only these engine args are allowed, same format as `deny`
+    pub allow: Vec<String>,
+}
+
+/// Rules for host paths the user chooses to bind mount (mounts, skel layers and env files), the
+/// workspace is not affected
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct MountsPolicy {
+    /// If not empty only paths inside these directories can be mounted, must be absolute
+    pub allow: Vec<String>,
+
+    /// Mounts have to be read-only
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct PermissionsPolicy {
+    /// Permissions that cannot be enabled (ex. `session_bus`, `network`)
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct CapabilitiesPolicy {
+    /// If set only these capabilities can be added
+    pub allow: Option<Vec<String>>,
+}
+
+/// Rules that apply only to images matching the pattern
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ImagePolicy {
+    /// Image pattern, supports `*` and `?` wildcards
+    pub pattern: String,
+
+    pub permissions: PermissionsPolicy,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Policy {
+    /// Path to the policy file
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+
+    /// Forbid configs with `host_pre_init` as it runs arbitrary code on the host
+    pub deny_host_pre_init: bool,
+
+    /// If not empty images must be from one of these registries (or repositories), supports
+    /// wildcards
+    pub registries: Vec<String>,
+
+    pub permissions: PermissionsPolicy,
+
+    pub capabilities: CapabilitiesPolicy,
+
+    pub images: Vec<ImagePolicy>,
+
+    pub mounts: MountsPolicy,
+
+    pub engine_args: EngineArgsPolicy,
+}
+
+/// Check if engine arg matches the rule, rule is either just a flag or a flag with value
+fn matches_rule(rule: &str, flag: &str, value: Option<&str>) -> bool {
+    match rule.split_once("=") {
+        Some((rule_flag, rule_value)) => {
+            normalize_flag(rule_flag) == flag && value == Some(rule_value)
+        }
+        None => normalize_flag(rule) == flag,
+    }
+}
+
+/// Converts capability into the canonical form, uppercase without the `CAP_` prefix
+fn normalize_capability(cap: &str) -> String {
+    let cap = cap.to_uppercase();
+    cap.strip_prefix("CAP_").unwrap_or(&cap).to_string()
+}
+
+/// Returns registry of the image if it is fully qualified
+fn image_registry(image: &str) -> Option<&str> {
+    let (registry, _) = image.split_once('/')?;
+
+    // same heuristic as the engines use to tell registry from a repository
+    if registry.contains(['.', ':']) || registry == "localhost" {
+        Some(registry)
+    } else {
+        None
+    }
+}
+
+/// Check if image is from the registry (or repository), wildcards are matched against each path
+/// segment separately so they cannot match across `/`
+fn registry_matches(registry: &str, image: &str) -> bool {
+    let registry = registry
+        .trim_end_matches('/')
+        .split('/')
+        .collect::<Vec<_>>();
+    let image = image.split('/').collect::<Vec<_>>();
+
+    // the image name itself has to follow
+    image.len() > registry.len()
+        && registry
+            .iter()
+            .zip(&image)
+            .all(|(pattern, segment)| glob_match(pattern, segment))
+}
+
+impl Policy {
+    /// Load the policy from file, if the file does not exist default policy is used
+    pub fn from_file(file: &Path) -> Result<Self> {
+        if !file.exists() {
+            log::debug!("Policy file {file:?} does not exist, using default policy");
+            return Ok(Self::default());
+        }
+
+        let file_contents = std::fs::read_to_string(file)
+            .with_context(|| format!("while reading policy file {:?}", file))?;
+
+        let mut policy = toml::from_str::<Self>(&file_contents)
+            .with_context(|| format!("while parsing policy file {:?}", file))?;
+
+        policy.path = Some(file.to_path_buf());
+        policy
+            .validate()
+            .with_context(|| format!("while parsing policy file {:?}", file))?;
+
+        Ok(policy)
+    }
+
+    /// Check for typos in permission names, otherwise the rule would silently do nothing
+    fn validate(&self) -> Result<()> {
+        let denied = self
+            .permissions
+            .deny
+            .iter()
+            .chain(self.images.iter().flat_map(|x| &x.permissions.deny));
+
+        for i in denied {
+            if !CmdStartArgs::PERMISSIONS.contains(&i.as_str()) {
+                return Err(anyhow!(
+                    "Invalid permission {:?}, valid permissions are: {}",
+                    i,
+                    CmdStartArgs::PERMISSIONS.join(", ")
+                ));
+            }
+        }
+
+        // relative paths would depend on the cwd
+        for i in &self.mounts.allow {
+            if !Path::new(i).is_absolute() {
+                return Err(anyhow!("Allowed mount path {:?} must be absolute", i));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Error for violation of a policy rule
+    fn violation(&self, rule: &str, message: String) -> anyhow::Error {
+        anyhow!(
+            "{}, blocked by policy rule `{}` ({:?})",
+            message,
+            rule,
+            self.path.as_deref().unwrap_or(Path::new(""))
+        )
+    }
+
+    /// Check if config is allowed to use `host_pre_init`
+    pub fn check_host_pre_init(&self) -> Result<()> {
+        if self.deny_host_pre_init {
+            return Err(self.violation(
+                "deny_host_pre_init",
+                "Config uses host_pre_init".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check the final start args (config already applied) and image against the policy
+    pub fn check_start(&self, cli_args: &CmdStartArgs, image: &str) -> Result<()> {
+        if !self.registries.is_empty() {
+            if image_registry(image).is_none() {
+                return Err(self.violation(
+                    "registries",
+                    format!(
+                        "Image {:?} is not fully qualified (ex. docker.io/library/{})",
+                        image, image
+                    ),
+                ));
+            }
+
+            if !self.registries.iter().any(|x| registry_matches(x, image)) {
+                return Err(self.violation(
+                    "registries",
+                    format!("Image {:?} is not from an allowed registry", image),
+                ));
+            }
+        }
+
+        let engine_args = parse_engine_args(&cli_args.engine_args);
+
+        // network can also be enabled using engine args
+        let network_arg = engine_args
+            .iter()
+            .any(|(flag, value)| flag == "--network" && value.as_deref() != Some("none"));
+
+        for (name, enabled) in cli_args.permissions() {
+            let enabled = enabled || (name == "network" && network_arg);
+            if !enabled {
+                continue;
+            }
+
+            if self.permissions.deny.iter().any(|x| x == name) {
+                return Err(self.violation(
+                    "permissions.deny",
+                    format!("Permission {:?} is not allowed", name),
+                ));
+            }
+
+            for (index, rule) in self.images.iter().enumerate() {
+                if glob_match(&rule.pattern, image)
+                    && rule.permissions.deny.iter().any(|x| x == name)
+                {
+                    return Err(self.violation(
+                        &format!("images[{}].permissions.deny", index),
+                        format!(
+                            "Permission {:?} is not allowed for image {:?} (matched {:?})",
+                            name, image, rule.pattern
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if let Some(allowed) = &self.capabilities.allow {
+            let allowed = allowed
+                .iter()
+                .map(|x| normalize_capability(x))
+                .collect::<Vec<_>>();
+
+            // last mention of capability wins same as in start
+            let mut caps: Vec<(String, bool)> = vec![];
+            for i in &cli_args.capabilities {
+                let (cap, add) = match i.strip_prefix("!") {
+                    Some(x) => (normalize_capability(x), false),
+                    None => (normalize_capability(i), true),
+                };
+
+                caps.retain(|(x, _)| *x != cap);
+                caps.push((cap, add));
+            }
+
+            let added = caps
+                .into_iter()
+                .filter_map(|(cap, add)| add.then_some(cap))
+                .chain(
+                    engine_args
+                        .iter()
+                        .filter(|(flag, _)| flag == "--cap-add")
+                        .filter_map(|(_, value)| value.as_deref().map(normalize_capability)),
+                );
+
+            for cap in added {
+                if !allowed.contains(&cap) {
+                    return Err(self.violation(
+                        "capabilities.allow",
+                        format!("Capability {:?} is not allowed", cap),
+                    ));
+                }
+            }
+        }
+
+        for mount in &cli_args.mount {
+            self.check_mount("Mount", Path::new(&mount.source), mount.readonly)?;
+        }
+
+        // skel layers and env files are only read
+        for skel in &cli_args.skel {
+            self.check_mount("Skel layer", Path::new(skel), true)?;
+        }
+
+        for file in &cli_args.env_file {
+            self.check_mount("Env file", Path::new(file), true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check host path that is going to be mounted (or read) against the mounts policy
+    fn check_mount(&self, kind: &str, source: &Path, readonly: bool) -> Result<()> {
+        if self.mounts.readonly && !readonly {
+            return Err(self.violation(
+                "mounts.readonly",
+                format!("{} {:?} is not read-only", kind, source),
+            ));
+        }
+
+        if self.mounts.allow.is_empty() {
+            return Ok(());
+        }
+
+        // symlinks and `..` could escape the allowed directories
+        let resolved = source
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {} {:?}", kind.to_lowercase(), source))?;
+
+        if !self.mounts.allow.iter().any(|x| resolved.starts_with(x)) {
+            return Err(self.violation(
+                "mounts.allow",
+                format!("{} {:?} is not allowed", kind, source),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check engine args against the policy, `managed` are the args set by arcam itself
+    pub fn check_engine_args(&self, managed: &[String], engine_args: &[String]) -> Result<()> {
+        let policy = &self.engine_args;
+
+        for (flag, value) in parse_engine_args(engine_args) {
+            let arg = format_arg(&flag, value.as_deref());
+
+            if policy
+                .deny
+                .iter()
+                .any(|x| matches_rule(x, &flag, value.as_deref()))
+            {
+                return Err(self.violation(
+                    "engine_args.deny",
+                    format!("Engine arg {:?} is not allowed", arg),
+                ));
+            }
+
+            if !policy.allow.is_empty()
+                && !policy
+                    .allow
+                    .iter()
+                    .any(|x| matches_rule(x, &flag, value.as_deref()))
+            {
+                return Err(self.violation(
+                    "engine_args.allow",
+                    format!("Engine arg {:?} is not allowed", arg),
+                ));
+            }
+        }
+
+        for (arg, reason) in find_conflicts(managed, engine_args) {
+            match policy.on_conflict {
+                PolicyAction::Ignore => {}
+                PolicyAction::Warn => log::warn!("Engine arg {arg:?} {reason}"),
+                PolicyAction::Error => {
+                    return Err(self.violation(
+                        "engine_args.on_conflict",
+                        format!("Engine arg {:?} {}", arg, reason),
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start_args(args: &[&str]) -> CmdStartArgs {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Wrapper {
+            #[command(flatten)]
+            args: CmdStartArgs,
+        }
+
+        // image is required but it is not used
+        Wrapper::parse_from(["arcam", "debian"].iter().chain(args)).args
+    }
+
+    #[test]
+    fn policy_start() {
+        let policy = toml::from_str::<Policy>(
+            r#"
+registries = [ "docker.io/library", "*.example.com" ]
+
+[permissions]
+deny = [ "session_bus" ]
+
+[capabilities]
+allow = [ "NET_BIND_SERVICE" ]
+
+[[images]]
+pattern = "docker.io/*"
+permissions.deny = [ "network" ]
+"#,
+        )
+        .unwrap();
+        assert!(policy.validate().is_ok());
+
+        let check = |args: &[&str], image: &str| policy.check_start(&start_args(args), image);
+
+        assert!(check(&[], "registry.example.com/fedora").is_ok());
+        assert!(check(&["--network"], "registry.example.com/fedora").is_ok());
+        assert!(check(&[], "debian").is_err());
+        assert!(check(&[], "ghcr.io/fedora").is_err());
+        assert!(check(&[], "evil.com/x.example.com/foo").is_err());
+        assert!(check(&[], "example.com.evil.com/foo").is_err());
+        assert!(check(&[], "docker.io/library").is_err());
+        assert!(check(&[], "docker.io/evil/library/debian").is_err());
+        assert!(check(&["--network"], "docker.io/library/debian").is_err());
+        assert!(check(&["--session-bus"], "registry.example.com/fedora").is_err());
+        assert!(check(
+            &["--cap", "cap_net_bind_service"],
+            "docker.io/library/debian"
+        )
+        .is_ok());
+        assert!(check(&["--cap", "NET_RAW,!NET_RAW"], "docker.io/library/debian").is_ok());
+        assert!(check(&["--cap", "SYS_ADMIN"], "docker.io/library/debian").is_err());
+        assert!(check(&["--", "--cap-add=SYS_ADMIN"], "docker.io/library/debian").is_err());
+
+        let policy = toml::from_str::<Policy>(
+            r#"
+[permissions]
+deny = [ "sesion_bus" ]
+"#,
+        )
+        .unwrap();
+        assert!(policy.validate().is_err());
+    }
+
+    #[test]
+    fn policy_engine_args() {
+        let policy = toml::from_str::<Policy>(
+            r#"
+[engine_args]
+on_conflict = "error"
+deny = [ "--privileged", "--net=host" ]
+"#,
+        )
+        .unwrap();
+
+        let managed = ["--network=none".to_string()];
+        let check = |x: &[&str]| {
+            policy.check_engine_args(
+                &managed,
+                &x.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+            )
+        };
+
+        assert!(check(&["--device=/dev/kvm"]).is_ok());
+        assert!(check(&["--privileged"]).is_err());
+        assert!(check(&["--network", "host"]).is_err());
+        assert!(check(&["--network=slirp4netns"]).is_err());
+
+        let policy = toml::from_str::<Policy>(
+            r#"
+[engine_args]
+allow = [ "--device" ]
+"#,
+        )
+        .unwrap();
+
+        assert!(policy
+            .check_engine_args(&managed, &["--device=/dev/kvm".into()])
+            .is_ok());
+        assert!(policy
+            .check_engine_args(&managed, &["--pid=host".into()])
+            .is_err());
+    }
+
+    #[test]
+    fn policy_mounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let allowed = dir.path().join("allowed");
+        let denied = dir.path().join("denied");
+        std::fs::create_dir_all(allowed.join("project")).unwrap();
+        std::fs::create_dir_all(&denied).unwrap();
+        std::fs::write(denied.join(".env"), "TOKEN=hunter2").unwrap();
+        std::os::unix::fs::symlink(&denied, allowed.join("link")).unwrap();
+
+        let path = |x: &Path| x.to_string_lossy().to_string();
+        let project = path(&allowed.join("project"));
+
+        assert!(Policy::default()
+            .check_start(&start_args(&["--mount", "/"]), "debian")
+            .is_ok());
+
+        let policy = toml::from_str::<Policy>(&format!(
+            r#"
+[mounts]
+allow = [ {:?} ]
+"#,
+            path(&allowed)
+        ))
+        .unwrap();
+        assert!(policy.validate().is_ok());
+
+        let check = |args: &[&str]| policy.check_start(&start_args(args), "debian");
+
+        assert!(check(&[]).is_ok());
+        assert!(check(&["--mount", &project]).is_ok());
+        assert!(check(&["--mount", &format!("{}:/src:ro", project)]).is_ok());
+        assert!(check(&["--skel", &project]).is_ok());
+        assert!(check(&["--mount", "/"]).is_err());
+        assert!(check(&["--mount", &path(&denied)]).is_err());
+        assert!(check(&["--mount", &path(&allowed.join("project/../../denied"))]).is_err());
+        assert!(check(&["--mount", &path(&allowed.join("link"))]).is_err());
+        assert!(check(&["--skel", &path(&denied)]).is_err());
+        assert!(check(&["--env-file", &path(&denied.join(".env"))]).is_err());
+
+        let policy = toml::from_str::<Policy>(
+            r#"
+[mounts]
+readonly = true
+"#,
+        )
+        .unwrap();
+
+        let check = |args: &[&str]| policy.check_start(&start_args(args), "debian");
+
+        assert!(check(&["--mount", &format!("{}::ro", project)]).is_ok());
+        assert!(check(&["--skel", &project]).is_ok());
+        assert!(check(&["--mount", &project]).is_err());
+
+        let policy = toml::from_str::<Policy>(
+            r#"
+[mounts]
+allow = [ "relative" ]
+"#,
+        )
+        .unwrap();
+        assert!(policy.validate().is_err());
+    }
+}
@@ -1,4 +1,8 @@
 use crate::command_ext::command_extensions::*;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 /// Simple yes/no prompt
 pub fn prompt(prompt: &str) -> bool {
@@ -18,23 +22,193 @@ pub fn prompt(prompt: &str) -> bool {
     matches!(s.to_lowercase().as_str(), "y" | "yes")
 }
 
+/// Simple text prompt, returns trimmed input or `None` if stdin was closed
+pub fn prompt_input(prompt: &str) -> Option<String> {
+    use std::io::Write;
+    let mut s = String::new();
+
+    print!("{}: ", prompt);
+
+    let _ = std::io::stdout().flush();
+
+    let read = std::io::stdin()
+        .read_line(&mut s)
+        .expect("Could not read stdin");
+
+    if read == 0 {
+        return None;
+    }
+
+    Some(s.trim().to_string())
+}
+
 /// Check whether executable exists in PATH
 pub fn executable_in_path(cmd: &str) -> bool {
     let output = std::process::Command::new("sh")
         .arg("-c")
-        .arg(format!("which {}", cmd))
+        // which is not available on all images
+        .arg(format!("command -v {}", cmd))
         .log_output()
-        .expect("Failed to execute 'which'");
+        .expect("Failed to execute 'sh'");
 
     output.status.success()
 }
 
+/// Get hostname from system using `hostname` command
+pub fn get_hostname() -> Result<String> {
+    // try to get hostname from env var
+    if let Ok(env_hostname) = std::env::var("HOSTNAME") {
+        log::debug!("Getting hostname from environment");
+        return Ok(env_hostname);
+    }
+
+    log::debug!("Getting hostname using hostname command");
+
+    // then as a fallback use hostname executable
+    let cmd = Command::new("hostname")
+        .log_output()
+        .with_context(|| "Could not call hostname")?;
+
+    let hostname = String::from_utf8_lossy(&cmd.stdout);
+
+    if !cmd.status.success() || hostname.is_empty() {
+        return Err(anyhow!("Unable to get hostname from host"));
+    }
+
+    Ok(hostname.trim().into())
+}
+
 /// Check if running inside a container
 pub fn is_in_container() -> bool {
     use std::env;
-    use std::path::Path;
 
     Path::new("/run/.containerenv").exists()
         || Path::new("/.dockerenv").exists()
         || env::var("container").is_ok()
 }
+
+/// Simple glob matching, supports `*` (any number of characters) and `?` (any single character)
+pub fn glob_match(pattern: &str, input: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let input = input.chars().collect::<Vec<_>>();
+
+    // position of last star in pattern and input position it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut i) = (0, 0);
+
+    while i < input.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == input[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((star_p, star_i)) = star {
+            // backtrack and let the star consume one more character
+            p = star_p + 1;
+            i = star_i + 1;
+            star = Some((star_p, star_i + 1));
+        } else {
+            return false;
+        }
+    }
+
+    // only stars can be left
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Pairs of host and container paths, the same host path may be mounted multiple times
+pub type PathMapping = Vec<(String, String)>;
+
+/// Finds container path for `cwd` using the host to container path mapping, the deepest mount
+/// containing `cwd` wins
+pub fn resolve_workdir(mounts: &[(String, String)], cwd: &Path) -> Option<PathBuf> {
+    mounts
+        .iter()
+        .filter_map(|(host, container)| {
+            let rest = cwd.strip_prefix(host).ok()?;
+            Some((
+                Path::new(host).components().count(),
+                Path::new(container).join(rest),
+            ))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, path)| path)
+}
+
+/// Finds container path for `cwd` using the mounts label of the container
+pub fn container_workdir(labels: &HashMap<String, String>, cwd: &Path) -> Option<PathBuf> {
+    labels
+        .get(crate::CONTAINER_LABEL_MOUNTS)
+        .and_then(|x| serde_json::from_str::<PathMapping>(x).ok())
+        .and_then(|x| resolve_workdir(&x, cwd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("build-*", "build-01"));
+        assert!(glob_match("build-*", "build-"));
+        assert!(!glob_match("build-*", "laptop"));
+        assert!(glob_match("GITHUB_*", "GITHUB_TOKEN"));
+        assert!(glob_match("*.example.com/*", "ghcr.example.com/org/image"));
+        assert!(glob_match("node-??", "node-01"));
+        assert!(!glob_match("node-??", "node-1"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[test]
+    fn workdir() {
+        let mounts = [
+            (
+                "/home/user/proj".to_string(),
+                "/home/user/ws/proj".to_string(),
+            ),
+            (
+                "/home/user/b/lib".to_string(),
+                "/home/user/ws/lib-b".to_string(),
+            ),
+            (
+                "/home/user/proj/vendor".to_string(),
+                "/home/user/ws/vendor".to_string(),
+            ),
+        ];
+
+        let workdir = |x: &str| resolve_workdir(&mounts, Path::new(x));
+
+        assert_eq!(
+            workdir("/home/user/proj/src"),
+            Some(PathBuf::from("/home/user/ws/proj/src"))
+        );
+        assert_eq!(
+            workdir("/home/user/b/lib"),
+            Some(PathBuf::from("/home/user/ws/lib-b"))
+        );
+        assert_eq!(
+            workdir("/home/user/proj/vendor/x"),
+            Some(PathBuf::from("/home/user/ws/vendor/x"))
+        );
+        assert_eq!(workdir("/home/user/projects"), None);
+        assert_eq!(workdir("/tmp"), None);
+
+        // same source can be mounted multiple times
+        let labels = HashMap::from([(
+            crate::CONTAINER_LABEL_MOUNTS.to_string(),
+            r#"[["/home/user/proj","/home/user/ws/proj"],["/home/user/data","/home/user/ws/data"],["/home/user/data","/data"]]"#.to_string(),
+        )]);
+
+        assert_eq!(
+            container_workdir(&labels, Path::new("/home/user/data/x")),
+            Some(PathBuf::from("/data/x"))
+        );
+        assert_eq!(
+            container_workdir(&labels, Path::new("/home/user/proj")),
+            Some(PathBuf::from("/home/user/ws/proj"))
+        );
+        assert_eq!(container_workdir(&HashMap::new(), Path::new("/tmp")), None);
+    }
+}
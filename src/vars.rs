@@ -37,6 +37,15 @@ pub const CONTAINER_LABEL_CONTAINER_DIR: &str = "container_dir";
 /// Container label used to specify default shell
 pub const CONTAINER_LABEL_USER_SHELL: &str = "default_shell";
 
+/// Container label used to specify the host directory where workspace overlay is stored
+pub const CONTAINER_LABEL_OVERLAY_DIR: &str = "overlay_dir";
+
+/// Container label used to map host paths to paths in the container (JSON object)
+pub const CONTAINER_LABEL_MOUNTS: &str = "mounts";
+
+/// Container label used to store the effective config (JSON) without env var values
+pub const CONTAINER_LABEL_CONFIG: &str = "config";
+
 /// Set log level from the environ
 pub const ENV_LOG_LEVEL: &str = "LOG_LEVEL";
 
@@ -67,9 +76,31 @@ pub const ENV_CFG_DIR: &str = ENV_VAR_PREFIX!("CFG_DIR");
 /// Name of the config loaded
 pub const ENV_CFG_NAME: &str = ENV_VAR_PREFIX!("CFG_NAME");
 
+/// Privilege mode used inside the container
+pub const ENV_PRIVILEGE: &str = ENV_VAR_PREFIX!("PRIVILEGE");
+
+/// Paths chowned to the user on init (JSON array)
+pub const ENV_PERSIST_USER: &str = ENV_VAR_PREFIX!("PERSIST_USER");
+
+/// How skel layers are applied, see `SkelMode`
+pub const ENV_SKEL_MODE: &str = ENV_VAR_PREFIX!("SKEL_MODE");
+
 /// Where scripts are executed from
 pub const INIT_D_DIR: &str = "/init.d";
 
+/// Scripts in `INIT_D_DIR` with this suffix are executed as root
+pub const INIT_D_ROOT_SUFFIX: &str = ".root.sh";
+
+/// Where skel layers are mounted, each in its own numbered directory
+pub const SKEL_DIR: &str = "/arcam/skel";
+
+/// Variables used to render skel templates (JSON object), mounted as they may contain host
+/// environment values
+pub const SKEL_VARS_FILE: &str = "/arcam/skel.json";
+
+/// Where the package cache volume is mounted
+pub const PACKAGE_CACHE_DIR: &str = "/arcam/pkgcache";
+
 /// Path where all arcam related things should be
 pub const ARCAM_DIR: &str = "/arcam";
 
@@ -79,8 +110,11 @@ pub const ARCAM_EXE: &str = "/arcam/exe";
 /// Path to optional config file distributed within the image
 pub const ARCAM_CONFIG: &str = "/config.toml";
 
-/// This file existing is a signal when container initialization is finished
-pub const FLAG_FILE_INIT: &str = "/arcam/initialized";
+/// Output of the container initialization
+pub const INIT_LOG_FILE: &str = "/arcam/init.log";
+
+/// Progress of the container initialization (JSON), see `InitStatus`
+pub const INIT_STATUS_FILE: &str = "/arcam/status.json";
 
 /// This file existing is a signal start command has copied all data required in the container so
 /// the initialization can begin